@@ -40,7 +40,7 @@ fn benchmark_serialize(c: &mut Criterion) {
             "int0" => 0,
             "int1" => 10000,
             "int2" => -100000123,
-            "int4" => 5_000_000_000 as i64,
+            "int4" => 5_000_000_000_i64,
             "float0" => 13.2,
             "string0" => "foo",
             "string1" => "1.2.1",
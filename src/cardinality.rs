@@ -0,0 +1,168 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::{self, Write};
+use std::hash::Hasher;
+
+/// What happens to the tags of a key that exceeded its limit of distinct values
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CardinalityOverflow {
+    /// Writes all further values of the key as fields
+    #[default]
+    Field,
+    /// Writes new values of the key as the given value, e.g. `Replace("other")`. Values seen
+    /// before the limit was exceeded are still written
+    Replace(&'static str),
+}
+
+/// Closure called with the key of a tag that exceeded its limit of distinct values
+pub type CardinalityCallback = Box<dyn Fn(&str) + Send + Sync>;
+
+/// Limits the number of distinct values per tag key, since every tag value creates a series
+///
+/// Only hashes of the values are kept, for at most `max_keys` keys. Tags of further keys are
+/// not limited. The keys are tracked as written, i.e. after the `KeyMapping` and the resolution
+/// of key conflicts.
+///
+/// ```
+/// use slog_telegraf::{CardinalityGuard, CardinalityOverflow};
+///
+/// let guard = CardinalityGuard::new(100)
+///     .overflow(CardinalityOverflow::Replace("other"))
+///     .on_exceeded(|key| eprintln!("Too many values for tag {}", key));
+/// ```
+pub struct CardinalityGuard {
+    max_values: usize,
+    max_keys: usize,
+    overflow: CardinalityOverflow,
+    on_exceeded: Option<CardinalityCallback>,
+    keys: RefCell<HashMap<String, KeyValues>>,
+}
+
+#[derive(Default)]
+struct KeyValues {
+    hashes: HashSet<u64>,
+    exceeded: bool,
+}
+
+/// What is written for a tag, see `CardinalityGuard::check`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum Checked {
+    Tag,
+    Replace(&'static str),
+    Field,
+}
+
+impl CardinalityGuard {
+    /// Allows `max_values` distinct values per tag key, for up to 1000 keys
+    pub fn new(max_values: usize) -> Self {
+        CardinalityGuard {
+            max_values,
+            max_keys: 1000,
+            overflow: CardinalityOverflow::default(),
+            on_exceeded: None,
+            keys: RefCell::default(),
+        }
+    }
+
+    /// Sets the number of keys whose values are tracked
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    /// Sets what happens once a key exceeded its limit. Defaults to `CardinalityOverflow::Field`
+    pub fn overflow(mut self, overflow: CardinalityOverflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Calls the closure once for every key that exceeds its limit
+    pub fn on_exceeded<F>(mut self, on_exceeded: F) -> Self
+        where F: Fn(&str) + Send + Sync + 'static
+    {
+        self.on_exceeded = Some(Box::new(on_exceeded));
+        self
+    }
+
+    /// Records the tag value and returns what is written for it
+    pub(crate) fn check(&self, key: &str, val: &fmt::Arguments) -> Checked {
+        let mut hasher = HashWriter(DefaultHasher::new());
+        if hasher.write_fmt(*val).is_err() {
+            return Checked::Tag;
+        }
+        let hash = hasher.0.finish();
+
+        let checked = {
+            let mut keys = self.keys.borrow_mut();
+            let tracked = keys.len();
+            let values = match keys.get_mut(key) {
+                Some(values) => values,
+                None if tracked < self.max_keys => keys.entry(key.to_string()).or_default(),
+                None => return Checked::Tag
+            };
+
+            if values.exceeded {
+                return self.overflowed(values.hashes.contains(&hash));
+            }
+            if values.hashes.contains(&hash) {
+                return Checked::Tag;
+            }
+            if values.hashes.len() < self.max_values {
+                values.hashes.insert(hash);
+                return Checked::Tag;
+            }
+
+            values.exceeded = true;
+            if self.overflow == CardinalityOverflow::Field {
+                // The values are not needed anymore
+                values.hashes = HashSet::new();
+            }
+            self.overflowed(false)
+        };
+
+        if let Some(on_exceeded) = &self.on_exceeded {
+            on_exceeded(key);
+        }
+        checked
+    }
+
+    fn overflowed(&self, known: bool) -> Checked {
+        match self.overflow {
+            CardinalityOverflow::Field => Checked::Field,
+            CardinalityOverflow::Replace(_) if known => Checked::Tag,
+            CardinalityOverflow::Replace(other) => Checked::Replace(other)
+        }
+    }
+}
+
+/// Hashes everything written through it
+struct HashWriter(DefaultHasher);
+
+impl Write for HashWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.0.write(s.as_bytes());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_cardinality_guard() {
+        let guard = CardinalityGuard::new(2).max_keys(1);
+        assert_eq!(guard.check("a", &format_args!("{}", 1)), Checked::Tag);
+        assert_eq!(guard.check("a", &format_args!("{}", "1")), Checked::Tag);
+        assert_eq!(guard.check("a", &format_args!("{}{}", "x", "y")), Checked::Tag);
+        assert_eq!(guard.check("a", &format_args!("xy")), Checked::Tag);
+        assert_eq!(guard.check("a", &format_args!("z")), Checked::Field);
+        assert_eq!(guard.check("a", &format_args!("1")), Checked::Field);
+
+        // Keys beyond `max_keys` are not tracked
+        for i in 0..10 {
+            assert_eq!(guard.check("b", &format_args!("{}", i)), Checked::Tag);
+        }
+    }
+}
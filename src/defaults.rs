@@ -0,0 +1,242 @@
+use std::thread;
+use slog::{Level, Record, Serializer, KV};
+use crate::routing::Placement;
+
+/// Built-in values that describe the record or the process, see
+/// `TelegrafDrainBuilder::default_values`
+///
+/// The thread values describe the thread that calls the drain. Behind `slog_async`, this is the
+/// worker thread of the async drain.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefaultValue {
+    /// `level` - the level as integer, where `Critical` is 1 and `Trace` is 6
+    Level,
+    /// `level_name` - the level as lowercase name, e.g. `warning`
+    LevelName,
+    /// `severity` - the syslog severity of the level, e.g. 4 for `Warning`
+    SyslogSeverity,
+    /// `msg` - the logged message
+    Message,
+    /// `msg_template` - the message with its variable parts, i.e. quoted text and words
+    /// containing digits, replaced by `{}`. Allows to group by message without creating a series
+    /// per distinct message
+    MessageTemplate,
+    /// `mod` - the module of the logging statement, e.g. `your_crate::main`
+    Module,
+    /// `file` - the source file of the logging statement
+    File,
+    /// `line` - the line of the logging statement
+    Line,
+    /// `column` - the column of the logging statement
+    Column,
+    /// `function` - the function of the logging statement, if slog provides it
+    Function,
+    /// `tag` - the slog tag of the record, if any
+    Tag,
+    /// `thread` - the name of the thread, if it has one
+    ThreadName,
+    /// `thread_id` - the id of the thread
+    ThreadId,
+    /// `pid` - the id of the process
+    Pid,
+    /// `exe` - the file name of the executable
+    Executable,
+}
+
+impl DefaultValue {
+    /// The key the value is written with
+    pub fn key(self) -> &'static str {
+        match self {
+            DefaultValue::Level => "level",
+            DefaultValue::LevelName => "level_name",
+            DefaultValue::SyslogSeverity => "severity",
+            DefaultValue::Message => "msg",
+            DefaultValue::MessageTemplate => "msg_template",
+            DefaultValue::Module => "mod",
+            DefaultValue::File => "file",
+            DefaultValue::Line => "line",
+            DefaultValue::Column => "column",
+            DefaultValue::Function => "function",
+            DefaultValue::Tag => "tag",
+            DefaultValue::ThreadName => "thread",
+            DefaultValue::ThreadId => "thread_id",
+            DefaultValue::Pid => "pid",
+            DefaultValue::Executable => "exe",
+        }
+    }
+}
+
+fn level_name(level: Level) -> &'static str {
+    match level {
+        Level::Critical => "critical",
+        Level::Error => "error",
+        Level::Warning => "warning",
+        Level::Info => "info",
+        Level::Debug => "debug",
+        Level::Trace => "trace",
+    }
+}
+
+fn syslog_severity(level: Level) -> u8 {
+    match level {
+        Level::Critical => 2,
+        Level::Error => 3,
+        Level::Warning => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Appends the message with quoted text and words containing digits replaced by `{}`
+fn message_template(msg: &str, out: &mut String) {
+    let mut rest = msg;
+    while let Some(c) = rest.chars().next() {
+        if c.is_whitespace() {
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+            continue;
+        }
+
+        if c == '"' || c == '\'' {
+            if let Some(end) = rest[1..].find(c) {
+                out.push_str("{}");
+                rest = &rest[end + 2..];
+                continue;
+            }
+        }
+
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let word = &rest[..end];
+        rest = &rest[end..];
+
+        // Keep surrounding punctuation, e.g. in `(id 42).`
+        let core = word.trim_matches(|c: char| "([{".contains(c) || ")]},.;:!?".contains(c));
+        if core.bytes().any(|b| b.is_ascii_digit()) {
+            let start = word.find(core).unwrap_or(0);
+            out.push_str(&word[..start]);
+            out.push_str("{}");
+            out.push_str(&word[start + core.len()..]);
+        } else {
+            out.push_str(word);
+        }
+    }
+}
+
+/// The default values selected on the builder, each with its placement
+#[derive(Clone, Debug, Default)]
+pub(crate) struct DefaultValues {
+    values: Vec<(DefaultValue, Placement)>,
+    /// The file name of the executable, looked up once
+    executable: Option<String>,
+}
+
+impl DefaultValues {
+    /// Adds the value, or changes its placement if it was added before, so that no value is
+    /// written twice
+    pub fn add(&mut self, value: DefaultValue, placement: Placement) {
+        if value == DefaultValue::Executable && self.executable.is_none() {
+            self.executable = std::env::current_exe().ok()
+                .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into_owned()));
+        }
+
+        match self.values.iter_mut().find(|(v, _)| *v == value) {
+            Some((_, p)) => *p = placement,
+            None => self.values.push((value, placement))
+        }
+    }
+
+    pub fn contains(&self, value: DefaultValue) -> bool {
+        self.values.iter().any(|(v, _)| *v == value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn placement(&self, value: DefaultValue) -> Option<Placement> {
+        self.values.iter().find(|(v, _)| *v == value).map(|&(_, p)| p)
+    }
+
+    /// Serializes the values with the given placement, except `skip`
+    pub fn serialize_placed(&self, rinfo: &Record, placement: Placement, skip: Option<DefaultValue>,
+                            serializer: &mut dyn Serializer) -> slog::Result {
+        for &(value, _) in self.values.iter().filter(|&&(v, p)| p == placement && Some(v) != skip) {
+            self.emit(rinfo, value, serializer)?;
+        }
+        Ok(())
+    }
+
+    pub fn emit(&self, rinfo: &Record, value: DefaultValue, serializer: &mut dyn Serializer) -> slog::Result {
+        let key = value.key();
+        match value {
+            DefaultValue::Level => serializer.emit_usize(key, rinfo.level().as_usize()),
+            DefaultValue::LevelName => serializer.emit_str(key, level_name(rinfo.level())),
+            DefaultValue::SyslogSeverity => serializer.emit_u8(key, syslog_severity(rinfo.level())),
+            DefaultValue::Message => serializer.emit_arguments(key, rinfo.msg()),
+            DefaultValue::MessageTemplate => {
+                let mut template = String::new();
+                match rinfo.msg().as_str() {
+                    Some(msg) => message_template(msg, &mut template),
+                    None => message_template(&rinfo.msg().to_string(), &mut template)
+                }
+                serializer.emit_str(key, &template)
+            },
+            DefaultValue::Module => serializer.emit_str(key, rinfo.module()),
+            DefaultValue::File => serializer.emit_str(key, rinfo.file()),
+            DefaultValue::Line => serializer.emit_u32(key, rinfo.line()),
+            DefaultValue::Column => serializer.emit_u32(key, rinfo.column()),
+            DefaultValue::Function if rinfo.function().is_empty() => Ok(()),
+            DefaultValue::Function => serializer.emit_str(key, rinfo.function()),
+            DefaultValue::Tag if rinfo.tag().is_empty() => Ok(()),
+            DefaultValue::Tag => serializer.emit_str(key, rinfo.tag()),
+            DefaultValue::ThreadName => match thread::current().name() {
+                Some(name) => serializer.emit_str(key, name),
+                None => Ok(())
+            },
+            DefaultValue::ThreadId => {
+                // `ThreadId::as_u64` is unstable, so the number is taken from the debug output
+                let id = format!("{:?}", thread::current().id());
+                let number = id.trim_start_matches("ThreadId(").trim_end_matches(')');
+                match number.parse::<u64>() {
+                    Ok(number) => serializer.emit_u64(key, number),
+                    Err(_) => serializer.emit_str(key, &id)
+                }
+            },
+            DefaultValue::Pid => serializer.emit_u32(key, std::process::id()),
+            DefaultValue::Executable => match &self.executable {
+                Some(executable) => serializer.emit_str(key, executable),
+                None => Ok(())
+            }
+        }
+    }
+}
+
+/// Serializes all values, regardless of their placement
+impl KV for DefaultValues {
+    fn serialize(&self, rinfo: &Record, serializer: &mut dyn Serializer) -> slog::Result {
+        for &(value, _) in &self.values {
+            self.emit(rinfo, value, serializer)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_message_template() {
+        let template = |msg| {
+            let mut template = String::new();
+            message_template(msg, &mut template);
+            template
+        };
+
+        assert_eq!(template("request done"), "request done");
+        assert_eq!(template("user 42 logged in from 10.0.0.1"), "user {} logged in from {}");
+        assert_eq!(template("failed to open \"/tmp/a b\": error (code 2)."), "failed to open {}: error (code {}).");
+        assert_eq!(template("it's 'x y' id=5f3a  v2"), "it's {} {}  {}");
+        assert_eq!(template("unterminated \"quote 1"), "unterminated \"quote {}");
+    }
+}
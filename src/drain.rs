@@ -1,20 +1,58 @@
-use std::io;
+use std::borrow::Cow;
 use std::cell::RefCell;
-use slog::{FnValue, PushFnValue};
+use std::sync::Arc;
+use std::time::SystemTime;
 use slog::{OwnedKVList, KV, SendSyncRefUnwindSafeKV};
-use slog::{Record, o};
-use crate::{Client, Error};
-use crate::ser::{TelegrafSocketSerializer};
+use slog::{Record, Serializer};
+use crate::{Client, Error, LineError};
+use crate::cardinality::CardinalityGuard;
+use crate::defaults::{DefaultValue, DefaultValues};
+use crate::keys::{KeyConflicts, KeyMapping, KeyResolver, Source};
+use crate::routing::{Placement, Router, Routing};
+use crate::validate;
+#[cfg(feature = "nested-values")]
+use crate::nested::NestedValues;
+use crate::ser::{TelegrafSocketSerializer, SerializerConfig, UnsignedIntegers, Precision, ValueFinder};
+use crate::ser::{NonFiniteFloats, NonFiniteFloatCounters, ValueEncoding, LengthLimits, ErrorValues};
+
+
+/// What is written for records without fields, since InfluxDB requires at least one field per line
+#[derive(Clone, Debug, Default)]
+pub enum EmptyFields {
+    /// Writes the field `_dummy=1i`
+    #[default]
+    Dummy,
+    /// Writes the given values as fields, e.g. `EmptyFields::Custom(o!("event" => true).into())`
+    Custom(OwnedKVList),
+    /// Writes the log message as string field with the given key, e.g. `message="log"`
+    ///
+    /// The message tag of `TelegrafDrainBuilder::default_tags` is omitted from such records, so
+    /// the message is not written twice.
+    Message(&'static str),
+    /// Writes an integer field with the given key and the value 1, which allows to count records
+    /// by summing up the field
+    Count(&'static str),
+    /// Drops records without fields instead of sending them
+    Drop,
+}
 
+/// Closure that chooses the measurement of a record, see `TelegrafDrainBuilder::measurement_fn`
+type MeasurementFn = Box<dyn Fn(&Record) -> Option<String> + Send + Sync>;
 
 /// Telegraf `Drain` for `slog-rs`.
 ///
+/// Logging fails transiently while the connection to Telegraf is re-established, see
+/// `Client::backoff`. Wrap the drain with `ignore_res` or `map_err` rather than `fuse`, which
+/// panics on the first failed record.
+///
 /// ``` no_run
 ///use slog::{Logger, Drain, o, info};
 ///use slog_telegraf::{TelegrafDrain};
 ///
 ///fn main() {
-///    let drain = TelegrafDrain::new("tcp://127.0.0.1:8094".into(), "measurement".into()).unwrap().fuse();
+///    // Writes fail while Telegraf is unavailable, e.g. while it restarts. Such records are dropped
+///    // instead of panicking like `fuse` would
+///    let drain = TelegrafDrain::new("tcp://127.0.0.1:8094".into(), "measurement".into()).unwrap().ignore_res();
 ///    let drain = slog_async::Async::new(drain).build().fuse();
 ///
 ///    let log = Logger::root(drain, o!("ver" => "1.2.1"));
@@ -23,40 +61,136 @@ use crate::ser::{TelegrafSocketSerializer};
 /// ```
 pub struct TelegrafDrain {
     values: Vec<OwnedKVList>,
+    default_values: DefaultValues,
     client: RefCell<Client>,
-    measurement: String
+    measurement: String,
+    measurement_fn: Option<MeasurementFn>,
+    config: SerializerConfig,
+    key_conflicts: KeyConflicts,
+    key_resolver: RefCell<KeyResolver>,
+    router: Router,
+    empty_fields: EmptyFields,
+    validate_lines: bool,
+    max_line_length: Option<usize>
 }
 
 impl TelegrafDrain {
     pub fn new(url: String, measurement: String) -> Result<TelegrafDrain, Error>  {
         Ok(TelegrafDrainBuilder::new(Client::new(url)?, measurement).default_tags().build())
     }
+
+    /// Counters of the `NaN` and infinite float fields logged through this drain
+    pub fn non_finite_float_counters(&self) -> Arc<NonFiniteFloatCounters> {
+        self.config.non_finite_float_counters.clone().unwrap_or_default()
+    }
+
+    /// Returns the measurement the line is started with, from the measurement closure or the
+    /// fixed measurement. The serializer replaces it with the value of the measurement key, if any
+    fn measurement(&self, rinfo: &Record) -> Cow<'_, str> {
+        let measurement = self.measurement_fn.as_ref().and_then(|measurement_fn| measurement_fn(rinfo));
+        measurement.map_or(Cow::Borrowed(self.measurement.as_str()), Cow::Owned)
+    }
 }
 
 impl slog::Drain for TelegrafDrain  {
     type Ok = ();
-    type Err = io::Error;
-
-    fn log(&self, rinfo: &Record, logger_values: &OwnedKVList) -> io::Result<()> {
-        let mut serializer = TelegrafSocketSerializer::start(&self.measurement, None)?;
-        let mut tag_serializer = serializer.tag_serializer();
-
+    type Err = Error;
+
+    fn log(&self, rinfo: &Record, logger_values: &OwnedKVList) -> Result<(), Error> {
+        let time = SystemTime::now();
+
+        let measurement = self.measurement(rinfo);
+        let mut serializer = TelegrafSocketSerializer::start_with_config(&measurement, None, &self.config)?;
+        serializer.set_timestamp(time);
+
+        let namespace = match self.router.namespace_key {
+            Some(key) => {
+                let mut finder = ValueFinder { key, value: None };
+                logger_values.serialize(rinfo, &mut finder)?;
+                finder.value
+            },
+            None => None
+        };
+
+        let mut resolver = self.key_resolver.borrow_mut();
+        let mut keys = match self.key_conflicts {
+            KeyConflicts::Allow => None,
+            _ => {
+                let mapping = &self.config.key_mapping;
+                resolver.start(mapping, namespace.as_deref(), rinfo, &self.values, logger_values)?;
+                resolver.collect(Source::Builder, mapping, None, rinfo, &self.default_values)?;
+                Some(&mut *resolver)
+            }
+        };
+        serializer.set_namespace(namespace);
+
+        let (router, guard) = (Some(&self.router), self.router.cardinality.as_ref());
+        let mut tag_serializer = serializer.tag_serializer_for(Source::Builder, keys.as_deref_mut(), router, guard);
         for kv in &self.values {
             kv.serialize(rinfo, &mut tag_serializer)?;
         }
 
-        // NOTE: The logger values get serialized as tags
-        // If you want to change this behavior, move this line below serializer.tag_value_break()
-        // and before serializer.end()
+        // The message tag is deferred while it is unknown whether the message becomes the
+        // fallback field
+        let deferred = match self.empty_fields {
+            EmptyFields::Message(_) if self.default_values.placement(DefaultValue::Message) == Some(Placement::Tag) => {
+                Some(DefaultValue::Message)
+            },
+            _ => None
+        };
+
+        // The default values are placed explicitly, regardless of the routing
+        if !self.default_values.is_empty() {
+            let mut tag_serializer = serializer.tag_serializer_for(Source::Builder, keys.as_deref_mut(), None, guard);
+            self.default_values.serialize_placed(rinfo, Placement::Tag, deferred, &mut tag_serializer)?;
+            let mut field_serializer = serializer.field_serializer_for(Source::Builder, keys.as_deref_mut(), None, guard);
+            self.default_values.serialize_placed(rinfo, Placement::Field, None, &mut field_serializer)?;
+        }
+
+        // The logger values are serialized as tags and the record values as fields, unless the
+        // routing places them elsewhere
+        let mut tag_serializer = serializer.tag_serializer_for(Source::Logger, keys.as_deref_mut(), router, guard);
         logger_values.serialize(rinfo, &mut tag_serializer)?;
-        serializer.tag_value_break()?;
 
-        let mut field_serializer = serializer.field_serializer();
+        let mut field_serializer = serializer.field_serializer_for(Source::Record, keys.as_deref_mut(), router, guard);
         rinfo.kv().serialize(rinfo, &mut field_serializer)?;
 
-        let insert_dummy_field = field_serializer.skip_comma;
-        let data = serializer.end(insert_dummy_field)?;
-        self.client.borrow_mut().write(data.as_bytes())
+        if let Some(value) = deferred.filter(|_| serializer.has_fields()) {
+            let mut tag_serializer = serializer.tag_serializer_for(Source::Builder, keys, None, guard);
+            self.default_values.emit(rinfo, value, &mut tag_serializer)?;
+        }
+
+        if !serializer.has_fields() {
+            // The fallback fields are not checked for conflicts, since they only replace missing
+            // fields, and are written without namespace
+            serializer.set_namespace(None);
+            let mut field_serializer = serializer.field_serializer_for(Source::Record, None, None, None);
+            match &self.empty_fields {
+                EmptyFields::Dummy => field_serializer.emit_i64("_dummy", 1)?,
+                EmptyFields::Custom(kv) => kv.serialize(rinfo, &mut field_serializer)?,
+                EmptyFields::Message(key) => field_serializer.emit_arguments(key, rinfo.msg())?,
+                EmptyFields::Count(key) => field_serializer.emit_i64(key, 1)?,
+                EmptyFields::Drop => return Ok(())
+            }
+
+            // E.g. `EmptyFields::Custom` with values that are skipped as well
+            if !serializer.has_fields() {
+                return Err(Error::InvalidLine(LineError::MissingFields));
+            }
+        }
+
+        let data = serializer.end(false)?;
+        if self.validate_lines {
+            validate::validate(&data, self.max_line_length)?;
+        }
+
+        self.client.borrow_mut().write(data.as_bytes())?;
+        Ok(())
+    }
+
+    /// Posts the lines batched for an HTTP endpoint, see `Client::flush`
+    fn flush(&self) -> Result<(), slog::FlushError> {
+        self.client.borrow_mut().flush().map_err(slog::FlushError::from)
     }
 }
 
@@ -69,30 +203,62 @@ impl slog::Drain for TelegrafDrain  {
 /// let client = Client::new("tcp://127.0.0.1:8094".into()).unwrap();
 ///
 /// let drain = TelegrafDrainBuilder::new(client, "measurement".into())
-///                 .add_tag_kv(o!("key" => "value")).build().fuse();
+///                 .add_tag_kv(o!("key" => "value")).build().ignore_res();
 /// // ...
 /// ```
 pub struct TelegrafDrainBuilder {
     values: Vec<OwnedKVList>,
+    default_values: DefaultValues,
     client: Client,
-    measurement: String
+    measurement: String,
+    measurement_fn: Option<MeasurementFn>,
+    config: SerializerConfig,
+    key_conflicts: KeyConflicts,
+    router: Router,
+    empty_fields: EmptyFields,
+    validate_lines: bool,
+    max_line_length: Option<usize>
 }
 
 impl TelegrafDrainBuilder {
     pub fn new(client: Client, measurement: String) -> Self {
         TelegrafDrainBuilder {
             values: vec![],
+            default_values: DefaultValues::default(),
             client,
-            measurement
+            measurement,
+            measurement_fn: None,
+            config: SerializerConfig {
+                non_finite_float_counters: Some(Arc::default()),
+                ..Default::default()
+            },
+            key_conflicts: KeyConflicts::default(),
+            router: Router::default(),
+            empty_fields: EmptyFields::default(),
+            validate_lines: false,
+            max_line_length: None
         }
     }
 
     /// Build the 'Drain'
-    pub fn build(self) -> TelegrafDrain {
+    pub fn build(mut self) -> TelegrafDrain {
+        if let Some(precision) = self.config.timestamp_precision {
+            self.client.set_timestamp_precision(precision);
+        }
+
         TelegrafDrain {
             values: self.values,
+            default_values: self.default_values,
             client: RefCell::new(self.client),
-            measurement: self.measurement
+            measurement: self.measurement,
+            measurement_fn: self.measurement_fn,
+            config: self.config,
+            key_conflicts: self.key_conflicts,
+            key_resolver: RefCell::new(KeyResolver::new(self.key_conflicts)),
+            router: self.router,
+            empty_fields: self.empty_fields,
+            validate_lines: self.validate_lines,
+            max_line_length: self.max_line_length
         }
     }
 
@@ -104,27 +270,241 @@ impl TelegrafDrainBuilder {
         self
     }
 
+    /// Sets how unsigned integer fields are written, see `UnsignedIntegers`
+    ///
+    /// Defaults to `UnsignedIntegers::Saturate`. Use `UnsignedIntegers::Native` for InfluxDB 1.8+ or 2.x
+    pub fn unsigned_integers(mut self, unsigned_integers: UnsignedIntegers) -> Self {
+        self.config.unsigned_integers = unsigned_integers;
+        self
+    }
+
+    /// Appends the time the record reached the drain to every line, with the given precision
+    ///
+    /// Lines have no timestamp by default, so Telegraf uses the time it received them. Telegraf
+    /// expects nanoseconds, unless its `precision` is set otherwise.
+    pub fn timestamp_precision(mut self, precision: Precision) -> Self {
+        self.config.timestamp_precision = Some(precision);
+        self
+    }
+
+    /// Omits the timestamp again, so Telegraf uses the time the line was received
+    pub fn without_timestamps(mut self) -> Self {
+        self.config.timestamp_precision = None;
+        self
+    }
+
+    /// Sets how `NaN` and infinite float fields are handled. Defaults to `NonFiniteFloats::Drop`
+    pub fn non_finite_floats(mut self, non_finite_floats: NonFiniteFloats) -> Self {
+        self.config.non_finite_floats = non_finite_floats;
+        self
+    }
+
+    /// Sets how booleans, `None` and `()` are written, e.g. `ValueEncoding::UNIFORM` to write them
+    /// the same way in tags and fields, see `ValueEncoding`
+    pub fn value_encoding(mut self, value_encoding: ValueEncoding) -> Self {
+        self.config.value_encoding = value_encoding;
+        self
+    }
+
+    /// Sorts the tags by key, as recommended by InfluxDB, and removes duplicate keys
+    ///
+    /// Of all tags with the same key, the first one serialized is kept. The builder tags are
+    /// serialized before the logger values, and slog serializes newer logger values first.
+    pub fn sort_tags(mut self) -> Self {
+        self.config.sort_tags = true;
+        self
+    }
+
+    /// Sets how values are handled whose key is used more than once, see `KeyConflicts`
+    ///
+    /// Defaults to `KeyConflicts::Allow`.
+    pub fn key_conflicts(mut self, key_conflicts: KeyConflicts) -> Self {
+        self.key_conflicts = key_conflicts;
+        self
+    }
+
+    /// Sets which values are written as tags and which as fields, see `Routing`
+    ///
+    /// Defaults to `Routing::Default`, which writes the builder and logger values as tags and the
+    /// record values as fields.
+    pub fn routing(mut self, routing: Routing) -> Self {
+        self.router.routing = routing;
+        self
+    }
+
+    /// Limits the length of tag values, string fields and whole lines, see `LengthLimits`
+    ///
+    /// Telegraf drops lines longer than its `max_line_size`, and UDP datagrams are limited as well.
+    pub fn length_limits(mut self, length_limits: LengthLimits) -> Self {
+        self.config.length_limits = length_limits;
+        self
+    }
+
+    /// Sets how nested values, i.e. values passed as `slog::Serde`, are flattened into dotted
+    /// keys, see `NestedValues`
+    #[cfg(feature = "nested-values")]
+    pub fn nested_values(mut self, nested_values: NestedValues) -> Self {
+        self.config.nested_values = nested_values;
+        self
+    }
+
+    /// Sets which fields are written in addition to the message of errors, see `ErrorValues`
+    ///
+    /// By default, the messages of the sources are written as `<key>_sources` field.
+    pub fn error_values(mut self, error_values: ErrorValues) -> Self {
+        self.config.error_values = error_values;
+        self
+    }
+
+    /// Limits the number of distinct values per tag key, see `CardinalityGuard`
+    pub fn cardinality_guard(mut self, guard: CardinalityGuard) -> Self {
+        self.router.cardinality = Some(guard);
+        self
+    }
+
+    /// Sets how keys are renamed and normalized before they are written, see `KeyMapping`
+    pub fn key_mapping(mut self, key_mapping: KeyMapping) -> Self {
+        self.router.namespace_key = key_mapping.namespace_key;
+        self.config.key_mapping = key_mapping;
+        self
+    }
+
+    /// Takes the measurement from the value with the given key, if the record or the logger has one
+    ///
+    /// The value of the record takes precedence over the logger value. The key itself is not
+    /// written, neither as tag nor as field. E.g. with `measurement_key("measurement")`, the record
+    /// `info!(log, "request"; "measurement" => "http")` is written to the measurement `http`.
+    pub fn measurement_key(mut self, key: &'static str) -> Self {
+        self.router.measurement_key = Some(key);
+        self
+    }
+
+    /// Chooses the measurement of each record with the closure, e.g. by level or module
+    ///
+    /// Records for which the closure returns `None` are written to the measurement given to the
+    /// builder. The measurement key, if set and found, takes precedence over the closure.
+    pub fn measurement_fn<F>(mut self, measurement_fn: F) -> Self
+        where F: Fn(&Record) -> Option<String> + Send + Sync + 'static
+    {
+        self.measurement_fn = Some(Box::new(measurement_fn));
+        self
+    }
+
+    /// Sets what is written for records without fields. Defaults to `EmptyFields::Dummy`
+    pub fn empty_fields(mut self, empty_fields: EmptyFields) -> Self {
+        self.empty_fields = empty_fields;
+        self
+    }
+
+    /// Validates every line against the line protocol before it is sent
+    ///
+    /// Invalid lines are not sent, instead the drain returns `Error::InvalidLine`. Lines longer
+    /// than `max_line_length` bytes, if given, are invalid as well.
+    pub fn validate_lines(mut self, max_line_length: Option<usize>) -> Self {
+        self.validate_lines = true;
+        self.max_line_length = max_line_length;
+        self
+    }
+
+    /// Adds built-in values, each written as tag or field as given, regardless of the routing
+    ///
+    /// ```no_run
+    /// use slog_telegraf::{TelegrafDrainBuilder, Client, DefaultValue, Placement};
+    ///
+    /// let client = Client::new("tcp://127.0.0.1:8094".into()).unwrap();
+    /// let builder = TelegrafDrainBuilder::new(client, "measurement".into()).default_values(&[
+    ///     (DefaultValue::LevelName, Placement::Tag),
+    ///     (DefaultValue::File, Placement::Field),
+    ///     (DefaultValue::Line, Placement::Field),
+    /// ]);
+    /// ```
+    pub fn default_values(mut self, values: &[(DefaultValue, Placement)]) -> Self {
+        for &(value, placement) in values {
+            self.default_values.add(value, placement);
+        }
+        self
+    }
+
     /// Adds default tags
     ///
     /// * `level` - record logging level integer, "Critical is the smallest and Trace the biggest value" - slog::Level, docs.rs/slog
     /// * `msg` - The logged message
     /// * `mod` - The source module of the log message, e.g. 'your_crate::main'
-    pub fn default_tags(self) -> Self {
-        self.add_tag_kv(o!(
-            "level" => FnValue(move |rinfo| rinfo.level().as_usize()),
-            "msg" => PushFnValue(move |record, ser| ser.emit(record.msg())),
-            "mod" => FnValue(move |rinfo| rinfo.module()),
-        ))
+    ///
+    /// See `default_values` for more values. Values added before keep their placement, e.g. the
+    /// message of `message_as_field`.
+    pub fn default_tags(mut self) -> Self {
+        for value in [DefaultValue::Module, DefaultValue::Message, DefaultValue::Level] {
+            if !self.default_values.contains(value) {
+                self.default_values.add(value, Placement::Tag);
+            }
+        }
+        self
+    }
+
+    /// Writes the message as string field `msg`, instead of as tag like `default_tags`
+    ///
+    /// Every distinct message tag creates a series. With `template_tag`, the tag `msg_template`
+    /// is added, which allows to group by message, see `DefaultValue::MessageTemplate`.
+    pub fn message_as_field(mut self, template_tag: bool) -> Self {
+        self.default_values.add(DefaultValue::Message, Placement::Field);
+        if template_tag {
+            self.default_values.add(DefaultValue::MessageTemplate, Placement::Tag);
+        }
+        self
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::LineError;
+    use crate::{Placement, KeyCase, CardinalityOverflow};
     use std::{thread, net};
     use std::sync::mpsc;
+    use std::time::Duration;
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use slog::{Logger, Drain};
 
+    /// Logs through a drain configured by `configure` and returns the line received
+    fn log_line<C, L>(configure: C, log: L) -> String
+        where C: FnOnce(TelegrafDrainBuilder) -> TelegrafDrainBuilder,
+              L: FnOnce(&Logger)
+    {
+        log_lines(configure, 1, log).remove(0)
+    }
+
+    /// Logs through a drain configured by `configure` and returns the first `count` lines received
+    fn log_lines<C, L>(configure: C, count: usize, log: L) -> Vec<String>
+        where C: FnOnce(TelegrafDrainBuilder) -> TelegrafDrainBuilder,
+              L: FnOnce(&Logger)
+    {
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let client = Client::new(format!("udp://{}", socket.local_addr().unwrap())).unwrap();
+        let drain = configure(TelegrafDrainBuilder::new(client, "test".into())).build();
+        log(&Logger::root(Mutex::new(drain).fuse(), o!()));
+
+        let mut buf = [0u8; 4096];
+        (0..count).map(|_| {
+            let len = socket.recv(&mut buf).unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        }).collect()
+    }
+
+    /// Removes the timestamp from a received line and checks that it is recent
+    fn strip_timestamp(line: &str) -> String {
+        let (line, timestamp) = line.trim_end_matches('\n').rsplit_once(' ').unwrap();
+        let timestamp: i128 = timestamp.parse().unwrap();
+
+        let now = Precision::Nanoseconds.timestamp(SystemTime::now());
+        assert!(timestamp <= now && now - timestamp < 60_000_000_000);
+
+        format!("{}\n", line)
+    }
+
     #[test]
     fn test_tcp_logging() {
         let (sender, receiver) = mpsc::channel();
@@ -179,4 +559,349 @@ mod test {
 
         assert_eq!(recvd_message, "test,mod=slog_telegraf::drain::test,msg=log,level=4,ver=1.2.1 testy=10i\n");
     }
+
+    #[test]
+    fn test_timestamps() {
+        let line = log_line(|builder| builder.timestamp_precision(Precision::Nanoseconds),
+                            |log| info!(log, "log"; "a" => 1));
+        assert_eq!(strip_timestamp(&line), "test a=1i\n");
+
+        let line = log_line(|builder| builder.timestamp_precision(Precision::Seconds).without_timestamps(),
+                            |log| info!(log, "log"; "a" => 1));
+        assert_eq!(line, "test a=1i\n");
+    }
+
+    #[test]
+    fn test_key_conflicts() {
+        let line = |key_conflicts| log_line(
+            |builder| builder.add_tag_kv(o!("env" => "prod")).key_conflicts(key_conflicts),
+            |log| info!(log.new(o!("env" => "dev", "user" => "a")), "log"; "user" => 1));
+
+        assert_eq!(line(KeyConflicts::Allow), "test,env=prod,user=a,env=dev user=1i\n");
+        assert_eq!(line(KeyConflicts::RecordWins), "test,env=dev user=1i\n");
+        assert_eq!(line(KeyConflicts::LoggerWins), "test,user=a,env=dev _dummy=1i\n");
+        assert_eq!(line(KeyConflicts::Rename("_ctx")), "test,env_ctx=prod,user_ctx=a,env=dev user=1i\n");
+
+        // Duplicates within the same source keep the newest value
+        let line = log_line(|builder| builder.key_conflicts(KeyConflicts::RecordWins),
+            |log| info!(log.new(o!("env" => "prod")).new(o!("env" => "dev")), "log"; "a" => 1, "a" => 2));
+        assert_eq!(line, "test,env=dev a=2i\n");
+    }
+
+    #[test]
+    fn test_key_conflicts_reject() {
+        let client = Client::new("udp://127.0.0.1:63744".into()).unwrap();
+        let drain = TelegrafDrainBuilder::new(client, "test".into()).key_conflicts(KeyConflicts::Reject).build();
+
+        let logger_values = OwnedKVList::from(o!("user" => "a"));
+        let result = drain.log(&record!(slog::Level::Info, "", &format_args!("log"), b!("user" => 1)), &logger_values);
+        assert!(matches!(result, Err(Error::InvalidLine(LineError::KeyConflict(key))) if key == "user"));
+        assert!(drain.log(&record!(slog::Level::Info, "", &format_args!("log"), b!("id" => 1)), &logger_values).is_ok());
+    }
+
+    #[test]
+    fn test_routing() {
+        let line = |routing| log_line(
+            |builder| builder.add_tag_kv(o!("env" => "prod")).routing(routing),
+            |log| info!(log.new(o!("request_id" => 7, "user" => "a")), "log"; "status" => 200, "ok" => true));
+
+        assert_eq!(line(Routing::Default), "test,env=prod,user=a,request_id=7 ok=t,status=200i\n");
+        assert_eq!(line(Routing::LoggerValuesAsFields), "test,env=prod user=\"a\",request_id=7i,ok=t,status=200i\n");
+        assert_eq!(line(Routing::TagAllowlist(vec!["env", "status"])), "test,env=prod,status=200 user=\"a\",request_id=7i,ok=t\n");
+        assert_eq!(line(Routing::TagDenylist(vec!["request_id"])), "test,env=prod,user=a request_id=7i,ok=t,status=200i\n");
+        assert_eq!(line(Routing::predicate(|key, source| match (key, source) {
+            ("ok", _) | (_, Source::Builder) => Placement::Tag,
+            _ => Placement::Field
+        })), "test,env=prod,ok=true user=\"a\",request_id=7i,status=200i\n");
+
+        // Routed tags are sorted with all other tags
+        let line = log_line(|builder| builder.routing(Routing::TagAllowlist(vec!["a", "b"])).sort_tags(),
+                            |log| info!(log.new(o!("b" => 2, "c" => 3)), "log"; "a" => 1));
+        assert_eq!(line, "test,a=1,b=2 c=3i\n");
+    }
+
+    #[test]
+    fn test_measurement() {
+        let line = |log: fn(&Logger)| log_line(|builder| builder.measurement_key("measurement"), log);
+
+        assert_eq!(line(|log| info!(log, "log"; "a" => 1)), "test a=1i\n");
+        assert_eq!(line(|log| info!(log, "log"; "measurement" => "http", "a" => 1)), "http a=1i\n");
+        assert_eq!(line(|log| info!(log.new(o!("measurement" => "db", "b" => 2)), "log"; "a" => 1)), "db,b=2 a=1i\n");
+        assert_eq!(line(|log| info!(log.new(o!("measurement" => "db")), "log"; "measurement" => "http", "a" => 1)), "http a=1i\n");
+        assert_eq!(line(|log| info!(log, "log"; "measurement" => "", "a" => 1)), "test a=1i\n");
+
+        // The measurement is truncated to the room left in the line
+        use crate::Measurement;
+        let limits = LengthLimits { line: Some(32), ..Default::default() };
+        let line = log_line(|builder| builder.measurement_key("measurement").length_limits(limits), |log| {
+            info!(log, "log"; "measurement" => "a_long_measurement_name", "a" => 1);
+        });
+        assert_eq!(line, "a_long_measur a=1i,_truncated=t\n");
+        let line = log_line(|builder| builder.length_limits(limits), |log| {
+            info!(log, "log"; "m" => Measurement("a_long_measurement_name"), "a" => 1);
+        });
+        assert_eq!(line, "a_long_measur a=1i,_truncated=t\n");
+        assert_eq!(line.len(), 32);
+
+        // The measurement is taken in the same pass as the other values
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        let line = log_line(|builder| builder.measurement_key("measurement"), move |log| {
+            let log = log.new(o!("measurement" => slog::FnValue(move |_| {
+                counted.fetch_add(1, Ordering::SeqCst);
+                "db"
+            })));
+            info!(log, "log"; "a" => 1);
+        });
+        assert_eq!(line, "db a=1i\n");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let line = |log: fn(&Logger)| log_line(|builder| builder.measurement_key("measurement")
+            .measurement_fn(|rinfo| match rinfo.level() {
+                slog::Level::Error => Some("errors".into()),
+                _ => None
+            }), log);
+
+        assert_eq!(line(|log| error!(log, "log"; "a" => 1)), "errors a=1i\n");
+        assert_eq!(line(|log| error!(log, "log"; "measurement" => "http", "a" => 1)), "http a=1i\n");
+        assert_eq!(line(|log| info!(log, "log"; "a" => 1)), "test a=1i\n");
+    }
+
+    #[test]
+    fn test_key_mapping() {
+        let mapping = KeyMapping {
+            renames: vec![("env", "environment")],
+            case: KeyCase::Snake,
+            replace_invalid: Some('_'),
+            namespace_key: Some("namespace"),
+            ..Default::default()
+        };
+        let line = |mapping: &KeyMapping| log_line(
+            |builder| builder.add_tag_kv(o!("env" => "prod")).key_mapping(mapping.clone()).key_conflicts(KeyConflicts::RecordWins),
+            |log| {
+                let log = log.new(o!("namespace" => "api", "requestId" => 7, "user_id" => "a"));
+                let log = log.new(o!("namespace" => "db"));
+                info!(log, "log"; "userId" => 1, "user id" => 2, "a,b" => 3);
+            });
+        // Keys that only conflict after the mapping keep the newest value
+        assert_eq!(line(&mapping), "test,environment=prod,user_id=a,request_id=7 db.a_b=3i,db.user_id=2i\n");
+
+        // The fallback fields of records without fields are not namespaced
+        let line = |empty_fields| log_line(|builder| builder.key_mapping(mapping.clone()).empty_fields(empty_fields),
+                                           |log| info!(log.new(o!("namespace" => "db")), "log"));
+        assert_eq!(line(EmptyFields::Dummy), "test _dummy=1i\n");
+        assert_eq!(line(EmptyFields::Count("count")), "test count=1i\n");
+    }
+
+    #[test]
+    fn test_cardinality_guard() {
+        let exceeded = Arc::new(Mutex::new(Vec::new()));
+        let exceeded_keys = exceeded.clone();
+        let log = |log: &Logger| for id in 0..4 {
+            info!(log.new(o!("id" => id, "host" => "a")), "log"; "n" => 1);
+        };
+
+        let lines = log_lines(|builder| builder.cardinality_guard(CardinalityGuard::new(2)
+            .on_exceeded(move |key| exceeded_keys.lock().unwrap().push(key.to_string()))), 4, log);
+        assert_eq!(lines, vec![
+            "test,host=a,id=0 n=1i\n",
+            "test,host=a,id=1 n=1i\n",
+            "test,host=a id=2i,n=1i\n",
+            "test,host=a id=3i,n=1i\n",
+        ]);
+        assert_eq!(*exceeded.lock().unwrap(), vec!["id"]);
+
+        let guard = CardinalityGuard::new(2).overflow(CardinalityOverflow::Replace("other"));
+        let lines = log_lines(|builder| builder.cardinality_guard(guard), 4, |log| {
+            for id in &[0, 1, 2, 1] {
+                info!(log.new(o!("id" => id)), "log"; "n" => 1);
+            }
+        });
+        assert_eq!(lines, vec!["test,id=0 n=1i\n", "test,id=1 n=1i\n", "test,id=other n=1i\n", "test,id=1 n=1i\n"]);
+
+        // The values are counted per name written
+        let exceeded = Arc::new(Mutex::new(Vec::new()));
+        let exceeded_keys = exceeded.clone();
+        let mapping = KeyMapping { case: KeyCase::Snake, ..Default::default() };
+        let lines = log_lines(|builder| builder.key_mapping(mapping).cardinality_guard(CardinalityGuard::new(2)
+            .on_exceeded(move |key| exceeded_keys.lock().unwrap().push(key.to_string()))), 3, |log| {
+            info!(log.new(o!("userId" => 0)), "log"; "n" => 1);
+            info!(log.new(o!("user-id" => 1)), "log"; "n" => 1);
+            info!(log.new(o!("userId" => 2)), "log"; "n" => 1);
+        });
+        assert_eq!(lines, vec!["test,user_id=0 n=1i\n", "test,user_id=1 n=1i\n", "test user_id=2i,n=1i\n"]);
+        assert_eq!(*exceeded.lock().unwrap(), vec!["user_id"]);
+
+        // Renamed keys are counted and written as field under their new name
+        let lines = log_lines(|builder| builder.key_conflicts(KeyConflicts::Rename("_logger"))
+            .cardinality_guard(CardinalityGuard::new(1)), 2, |log| {
+            info!(log.new(o!("id" => 0)), "log"; "id" => 5);
+            info!(log.new(o!("id" => 1)), "log"; "id" => 5);
+        });
+        assert_eq!(lines, vec!["test,id_logger=0 id=5i\n", "test id_logger=1i,id=5i\n"]);
+    }
+
+    #[test]
+    fn test_default_values() {
+        let line = log_line(|builder| builder.default_values(&[
+            (DefaultValue::LevelName, Placement::Tag),
+            (DefaultValue::SyslogSeverity, Placement::Tag),
+            (DefaultValue::Tag, Placement::Tag),
+            (DefaultValue::Function, Placement::Tag),
+            (DefaultValue::ThreadName, Placement::Tag),
+            (DefaultValue::Message, Placement::Field),
+            (DefaultValue::File, Placement::Field),
+            (DefaultValue::Level, Placement::Field),
+        ]), |log| {
+            let log = log.clone();
+            thread::Builder::new().name("worker".into())
+                .spawn(move || warn!(log, #"audit", "log"; "a" => 1)).unwrap()
+                .join().unwrap();
+        });
+        assert_eq!(line, "test,level_name=warning,severity=4,tag=audit,thread=worker msg=\"log\",file=\"src/drain.rs\",level=3i,a=1i\n");
+
+        let line = log_line(|builder| builder.default_values(&[
+            (DefaultValue::Pid, Placement::Field),
+            (DefaultValue::ThreadId, Placement::Field),
+            (DefaultValue::Executable, Placement::Tag),
+            (DefaultValue::Line, Placement::Field),
+        ]), |log| info!(log, "log"));
+        let exe = std::env::current_exe().unwrap();
+        let exe = exe.file_name().unwrap().to_str().unwrap();
+        assert!(line.starts_with(&format!("test,exe={} pid={}i,thread_id=", exe, std::process::id())), "{}", line);
+        assert!(line.contains(",line="), "{}", line);
+    }
+
+    #[test]
+    fn test_message_as_field() {
+        // The message is only written as field, regardless of the order of the calls
+        let line = log_line(|builder| builder.default_tags().message_as_field(false),
+                            |log| info!(log, "user {} logged in", 42));
+        assert_eq!(line, "test,mod=slog_telegraf::drain::test,level=4 msg=\"user 42 logged in\"\n");
+        let line = log_line(|builder| builder.message_as_field(false).default_tags(),
+                            |log| info!(log, "user {} logged in", 42; "a" => 1));
+        assert_eq!(line, "test,mod=slog_telegraf::drain::test,level=4 msg=\"user 42 logged in\",a=1i\n");
+
+        // Values added twice are written once, with the last placement
+        let line = log_line(|builder| builder.default_values(&[(DefaultValue::Level, Placement::Tag),
+                                                               (DefaultValue::Level, Placement::Field)]),
+                            |log| info!(log, "log"));
+        assert_eq!(line, "test level=4i\n");
+
+        let line = log_line(|builder| builder.message_as_field(true),
+                            |log| info!(log, "user {} logged in", 42; "a" => 1));
+        assert_eq!(line, "test,msg_template=user\\ {}\\ logged\\ in msg=\"user 42 logged in\",a=1i\n");
+    }
+
+    #[cfg(feature = "nested-values")]
+    #[test]
+    fn test_nested_values() {
+        use std::collections::BTreeMap;
+        use crate::{NestedValues, Sequences};
+
+        let mut http = BTreeMap::new();
+        http.insert("method", "GET");
+        http.insert("path", "/a b");
+        let nested = (http, vec![200u16, 304]);
+
+        let value = nested.clone();
+        let line = log_line(|builder| builder, move |log| {
+            let log = log.new(o!("req" => slog::Serde(value.clone())));
+            info!(log, "log"; "res" => slog::Serde(value));
+        });
+        assert_eq!(line, "test,req.0.method=GET,req.0.path=/a\\ b,req.1.0=200,req.1.1=304 \
+                          res.0.method=\"GET\",res.0.path=\"/a b\",res.1.0=200i,res.1.1=304i\n");
+
+        let mut codes = BTreeMap::new();
+        codes.insert("codes", vec![200u16, 304]);
+        codes.insert("ports", vec![80]);
+        let nested_values = NestedValues { max_keys: 1, sequences: Sequences::Join("|"), ..NestedValues::default() };
+        let line = log_line(|builder| builder.nested_values(nested_values),
+                            move |log| info!(log, "log"; "res" => slog::Serde(codes)));
+        assert_eq!(line, "test res.codes=\"200|304\",_truncated=t\n");
+    }
+
+    #[test]
+    fn test_wrapped_values() {
+        use crate::{Tag, Field, Measurement};
+
+        let line = log_line(|builder| builder.measurement_key("measurement"), |log| {
+            let log = log.new(o!("request_id" => Field(7), "measurement" => "http"));
+            info!(log, "log"; "region" => Tag("eu"), "measurement" => Measurement("db"));
+        });
+        assert_eq!(line, "db,region=eu request_id=7i\n");
+    }
+
+    #[test]
+    fn test_error_values() {
+        let error_values = ErrorValues { depth: Some("_depth"), ..ErrorValues::default() };
+        let line = log_line(|builder| builder.error_values(error_values), |log| {
+            let err = std::io::Error::new(std::io::ErrorKind::Other, "parse".parse::<u8>().unwrap_err());
+            info!(log, "log"; "err" => #err);
+        });
+        assert_eq!(line, "test err=\"invalid digit found in string\",err_depth=1i\n");
+    }
+
+    #[test]
+    fn test_empty_fields() {
+        let line = |empty_fields| log_line(|builder| builder.empty_fields(empty_fields),
+                                           |log| info!(log, "log"));
+
+        assert_eq!(line(EmptyFields::Dummy), "test _dummy=1i\n");
+        assert_eq!(line(EmptyFields::Custom(o!("event" => true, "kind" => "log").into())), "test kind=\"log\",event=t\n");
+        assert_eq!(line(EmptyFields::Message("message")), "test message=\"log\"\n");
+        assert_eq!(line(EmptyFields::Count("count")), "test count=1i\n");
+
+        // The message is not written as tag as well, unless the record has fields
+        let lines = log_lines(|builder| builder.default_tags().empty_fields(EmptyFields::Message("message")), 2, |log| {
+            info!(log, "log");
+            info!(log, "log"; "a" => 1);
+        });
+        assert_eq!(lines, vec!["test,mod=slog_telegraf::drain::test,level=4 message=\"log\"\n",
+                               "test,mod=slog_telegraf::drain::test,level=4,msg=log a=1i\n"]);
+
+        // Only the second record is sent
+        let line = log_line(|builder| builder.empty_fields(EmptyFields::Drop), |log| {
+            info!(log, "dropped");
+            info!(log, "sent"; "field" => 1);
+        });
+        assert_eq!(line, "test field=1i\n");
+
+        // The fallback fields do not conflict with the skipped fields of the record
+        let line = log_line(|builder| builder.key_conflicts(KeyConflicts::RecordWins)
+                                .empty_fields(EmptyFields::Custom(o!("a" => 1).into())),
+                            |log| info!(log, "log"; "a" => f64::NAN));
+        assert_eq!(line, "test a=1i\n");
+
+        let client = Client::new("udp://127.0.0.1:63746".into()).unwrap();
+        let drain = TelegrafDrainBuilder::new(client, "test".into()).empty_fields(EmptyFields::Custom(o!().into())).build();
+        let result = drain.log(&record!(slog::Level::Info, "", &format_args!("log"), b!()), &OwnedKVList::from(o!()));
+        assert!(matches!(result, Err(Error::InvalidLine(LineError::MissingFields))));
+    }
+
+    #[test]
+    fn test_validate_lines() {
+        // Valid lines are sent, so a receiver is required to not fail the later sends
+        let socket = net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(format!("udp://{}", socket.local_addr().unwrap())).unwrap();
+        let drain = TelegrafDrainBuilder::new(client, "test".into())
+            .non_finite_floats(NonFiniteFloats::Replace(f64::NAN))
+            .validate_lines(Some(100))
+            .build();
+
+        // Tags with empty values are omitted instead of failing the line
+        let logger_values = OwnedKVList::from(o!("empty" => ""));
+        let result = drain.log(&record!(slog::Level::Info, "", &format_args!("log"), b!("a" => 1)), &logger_values);
+        assert!(result.is_ok());
+
+        let logger_values = OwnedKVList::from(o!());
+        let result = drain.log(&record!(slog::Level::Info, "", &format_args!("log"), b!("a" => f64::NAN)), &logger_values);
+        assert!(matches!(result, Err(Error::InvalidLine(LineError::InvalidFieldValue(key))) if key == "a"));
+
+        let result = drain.log(&record!(slog::Level::Info, "", &format_args!("log"), b!("a" => "a".repeat(100))), &logger_values);
+        assert!(matches!(result, Err(Error::InvalidLine(LineError::TooLong { max_length: 100, .. }))));
+
+        let result = drain.log(&record!(slog::Level::Info, "", &format_args!("log"), b!("a" => 1)), &logger_values);
+        assert!(result.is_ok());
+    }
 }
@@ -4,7 +4,36 @@ use std::{io, error, fmt};
 pub enum Error {
     Io(io::Error),
     UrlParsing(url::ParseError),
-    Custom(String)
+    Custom(String),
+    /// The record could not be written as a valid line
+    InvalidLine(LineError)
+}
+
+/// Reasons why a record can not be written as a valid line of the line protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LineError {
+    /// The measurement is empty
+    EmptyMeasurement,
+    /// A tag or field key is empty
+    EmptyKey,
+    /// The value of the tag with the given key is empty
+    EmptyTagValue(String),
+    /// The tag or field key is reserved by InfluxDB
+    ReservedKey(String),
+    /// The line contains no fields
+    MissingFields,
+    /// The value of the field with the given key is not a valid integer, float, boolean or string
+    InvalidFieldValue(String),
+    /// The float field with the given key is `NaN` or infinite
+    NonFiniteFloat(String),
+    /// The key is used more than once, see `KeyConflicts::Reject`
+    KeyConflict(String),
+    /// The line is longer than the maximum length
+    TooLong { length: usize, max_length: usize },
+    /// The timestamp is not an integer
+    InvalidTimestamp,
+    /// The line does not follow the syntax of the line protocol
+    Malformed(String)
 }
 
 impl fmt::Display for Error {
@@ -12,7 +41,27 @@ impl fmt::Display for Error {
         match self {
             Error::Io(err) => err.fmt(f),
             Error::UrlParsing(err) => err.fmt(f),
-            Error::Custom(msg) => write!(f, "{}", msg)
+            Error::Custom(msg) => write!(f, "{}", msg),
+            Error::InvalidLine(err) => err.fmt(f)
+        }
+    }
+}
+
+impl fmt::Display for LineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LineError::EmptyMeasurement => write!(f, "The measurement is empty"),
+            LineError::EmptyKey => write!(f, "A key is empty"),
+            LineError::EmptyTagValue(key) => write!(f, "The value of the tag '{}' is empty", key),
+            LineError::ReservedKey(key) => write!(f, "The key '{}' is reserved", key),
+            LineError::MissingFields => write!(f, "The line contains no fields"),
+            LineError::InvalidFieldValue(key) => write!(f, "The value of the field '{}' is invalid", key),
+            LineError::NonFiniteFloat(key) => write!(f, "The float field '{}' is not finite", key),
+            LineError::KeyConflict(key) => write!(f, "The key '{}' is used more than once", key),
+            LineError::TooLong { length, max_length } =>
+                write!(f, "The line is {} bytes long, but at most {} bytes are allowed", length, max_length),
+            LineError::InvalidTimestamp => write!(f, "The timestamp is invalid"),
+            LineError::Malformed(msg) => write!(f, "The line is malformed: {}", msg)
         }
     }
 }
@@ -22,11 +71,14 @@ impl error::Error for Error {
         match self {
             Error::Io(err) => err.source(),
             Error::UrlParsing(_) |
-            Error::Custom(_) => None
+            Error::Custom(_) |
+            Error::InvalidLine(_) => None
         }
     }
 }
 
+impl error::Error for LineError {}
+
 impl From<url::ParseError> for Error {
     fn from(err: url::ParseError) -> Error {
         Error::UrlParsing(err)
@@ -37,4 +89,28 @@ impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         Error::Io(err)
     }
-}
\ No newline at end of file
+}
+
+impl From<LineError> for Error {
+    fn from(err: LineError) -> Error {
+        Error::InvalidLine(err)
+    }
+}
+
+impl From<slog::Error> for Error {
+    fn from(err: slog::Error) -> Error {
+        let err = io::Error::from(err);
+
+        // The serializers can only return slog::Error, so they wrap LineError into io::Error
+        match err.get_ref().and_then(|inner| inner.downcast_ref::<LineError>()) {
+            Some(line_error) => Error::InvalidLine(line_error.clone()),
+            None => Error::Io(err)
+        }
+    }
+}
+
+impl From<LineError> for slog::Error {
+    fn from(err: LineError) -> slog::Error {
+        slog::Error::Io(io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+}
@@ -0,0 +1,670 @@
+use std::{io, net, thread};
+use std::io::{Read, Write};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use url::Url;
+use crate::{Backoff, Error, Precision};
+
+/// Query parameters of the InfluxDB v1 `/write` endpoint, used for `http://` URLs
+///
+/// Parameters that are `None` are taken from the URL, if given there, e.g.
+/// `http://127.0.0.1:8086/write?db=logs`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WriteParameters {
+    /// The database, required by InfluxDB, ignored by Telegraf unless `database_tag` is set
+    pub db: Option<String>,
+    /// The retention policy
+    pub rp: Option<String>,
+    /// The precision of the timestamps. Replaced by `TelegrafDrainBuilder::timestamp_precision`
+    /// when the drain writes timestamps
+    pub precision: Option<Precision>,
+    /// The write consistency of an InfluxDB Enterprise cluster
+    pub consistency: Option<Consistency>,
+}
+
+/// Number of nodes of an InfluxDB Enterprise cluster that must confirm a write
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Consistency {
+    Any,
+    One,
+    Quorum,
+    All,
+}
+
+/// Limits of the batches of lines posted to an `http://` URL
+///
+/// A batch is posted by the logging call that fills it to `max_lines` or `max_bytes`, or by a
+/// background thread of the client once its first line has waited for `max_delay`, so that the
+/// lines of a quiet logger are not held back. Failures of the background thread are not reported,
+/// but retried like the ones of logging calls. `Client::flush` and `slog::Logger::flush` post the
+/// batch immediately, and the client posts it once more when it is dropped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Batching {
+    /// Defaults to 1000
+    pub max_lines: usize,
+    /// Defaults to 1 MiB
+    pub max_bytes: usize,
+    /// Defaults to 1 s
+    pub max_delay: Duration,
+}
+
+impl Default for Batching {
+    fn default() -> Self {
+        Batching {
+            max_lines: 1000,
+            max_bytes: 1 << 20,
+            max_delay: Duration::from_secs(1),
+        }
+    }
+}
+
+/// Posts batches of lines to a v1 compatible `/write` endpoint, e.g. InfluxDB 1.x, the
+/// compatibility API of InfluxDB 2.x or the `influxdb_listener` of Telegraf
+///
+/// Batches that wait for `Batching::max_delay` are posted by the flusher thread.
+pub(crate) struct HttpConnection {
+    shared: Arc<Shared>,
+    flusher: Option<thread::JoinHandle<()>>,
+}
+
+/// The state shared with the flusher thread
+struct Shared {
+    batcher: Mutex<Batcher>,
+    /// Wakes the flusher when a batch is started, the configuration changes or the connection is
+    /// dropped
+    wake: Condvar,
+}
+
+impl HttpConnection {
+    pub fn new(url: Url) -> Result<Self, Error> {
+        let shared = Arc::new(Shared { batcher: Mutex::new(Batcher::new(url)?), wake: Condvar::new() });
+        let flusher_shared = shared.clone();
+        let flusher = thread::Builder::new()
+            .name("slog-telegraf-http".into())
+            .spawn(move || flusher_shared.run())?;
+
+        Ok(HttpConnection { shared, flusher: Some(flusher) })
+    }
+
+    pub fn set_backoff(&self, backoff: Backoff) {
+        self.configure(|batcher| batcher.backoff = backoff);
+    }
+
+    pub fn set_batching(&self, batching: Batching) {
+        self.configure(|batcher| batcher.batching = batching);
+    }
+
+    pub fn set_timeout(&self, timeout: Duration) {
+        self.configure(|batcher| batcher.timeout = timeout);
+    }
+
+    pub fn set_parameters(&self, parameters: &WriteParameters) {
+        self.configure(|batcher| batcher.set_parameters(parameters));
+    }
+
+    /// Adds the line to the batch and posts the batch if it is full
+    ///
+    /// While a failed batch waits for its retry, lines are added until it reaches `max_bytes`. Further
+    /// lines are dropped with an error, so that the batch does not grow while InfluxDB or Telegraf
+    /// is unavailable.
+    pub fn write(&self, bytes: &[u8]) -> io::Result<()> {
+        let mut batcher = self.shared.lock();
+        if !batcher.batch.is_empty() && batcher.batch.len() + bytes.len() > batcher.batching.max_bytes {
+            batcher = self.shared.flush(batcher)?;
+        }
+
+        batcher.batch.extend_from_slice(bytes);
+        batcher.lines += 1;
+        if batcher.started.is_none() {
+            batcher.started = Some(Instant::now());
+            self.shared.wake.notify_all();
+        }
+
+        if batcher.full() && !batcher.waiting() {
+            return self.shared.flush(batcher).map(|_| ());
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> io::Result<()> {
+        self.shared.flush(self.shared.lock()).map(|_| ())
+    }
+
+    fn configure<F: FnOnce(&mut Batcher)>(&self, configure: F) {
+        configure(&mut self.shared.lock());
+        self.shared.wake.notify_all();
+    }
+}
+
+impl Drop for HttpConnection {
+    fn drop(&mut self) {
+        self.configure(|batcher| batcher.closed = true);
+        if let Some(flusher) = self.flusher.take() {
+            let _ = flusher.join();
+        }
+
+        // The lines still waiting are posted once more, regardless of the backoff
+        let pending = self.shared.lock().take();
+        if !pending.batch.is_empty() {
+            let _ = pending.post();
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Batcher> {
+        self.batcher.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, batcher: MutexGuard<'a, Batcher>) -> MutexGuard<'a, Batcher> {
+        self.wake.wait(batcher).unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Posts the batch, unless a failed post is waiting for its retry. The lock is released while
+    /// posting, so that lines can be written meanwhile. A batch posted by another thread is
+    /// awaited first
+    ///
+    /// Batches rejected with a 4xx status are dropped, since posting them again would fail again.
+    /// Batches that failed otherwise, e.g. with a 5xx status, 408 or 429, are retried after the
+    /// delays of the `Backoff`.
+    fn flush<'a>(&'a self, mut batcher: MutexGuard<'a, Batcher>) -> io::Result<MutexGuard<'a, Batcher>> {
+        while batcher.posting {
+            batcher = self.wait(batcher);
+        }
+        if batcher.batch.is_empty() {
+            return Ok(batcher);
+        }
+        if batcher.waiting() {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "Waiting to retry the write to Telegraf"));
+        }
+
+        let pending = batcher.take();
+        batcher.posting = true;
+        drop(batcher);
+
+        let response = pending.post();
+        let mut batcher = self.lock();
+        let result = batcher.finish(pending, response);
+        self.wake.notify_all();
+        result.map(|()| batcher)
+    }
+
+    /// Posts the batches that are due, until the connection is dropped
+    fn run(&self) {
+        let mut batcher = self.lock();
+        while !batcher.closed {
+            let now = Instant::now();
+            batcher = match batcher.due_at() {
+                // A failed batch is due again after the backoff
+                Some(due) if due <= now => self.flush(batcher).unwrap_or_else(|_| self.lock()),
+                Some(due) => self.wake.wait_timeout(batcher, due - now).unwrap_or_else(PoisonError::into_inner).0,
+                None => self.wait(batcher)
+            };
+        }
+    }
+}
+
+/// The batch and the configuration of an `HttpConnection`
+struct Batcher {
+    url: Url,
+    backoff: Backoff,
+    batching: Batching,
+    /// How long connecting, sending a batch and receiving the response may take
+    timeout: Duration,
+    batch: Vec<u8>,
+    lines: usize,
+    /// When the first line of the batch was written
+    started: Option<Instant>,
+    /// The number of failed posts of the batch
+    failures: u32,
+    /// The batch is not posted again before this time
+    retry_at: Option<Instant>,
+    /// Whether a batch is being posted, see `Shared::flush`
+    posting: bool,
+    /// Stops the flusher thread
+    closed: bool,
+}
+
+/// A batch taken from the `Batcher` to post it without holding the lock
+struct Pending {
+    url: Url,
+    timeout: Duration,
+    batch: Vec<u8>,
+    lines: usize,
+    started: Option<Instant>,
+}
+
+/// The status and body of a response
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Batcher {
+    fn new(mut url: Url) -> Result<Self, Error> {
+        if url.host_str().is_none() {
+            return Err(Error::Custom(format!("'{}' has no host", url)));
+        }
+        if url.path() == "/" {
+            url.set_path("/write");
+        }
+
+        Ok(Batcher {
+            url,
+            backoff: Backoff::default(),
+            batching: Batching::default(),
+            timeout: Duration::from_secs(5),
+            batch: Vec::new(),
+            lines: 0,
+            started: None,
+            failures: 0,
+            retry_at: None,
+            posting: false,
+            closed: false
+        })
+    }
+
+    /// Replaces the query parameters of the URL that are set in `parameters`
+    fn set_parameters(&mut self, parameters: &WriteParameters) {
+        let set = [
+            ("db", parameters.db.as_deref()),
+            ("rp", parameters.rp.as_deref()),
+            ("precision", parameters.precision.map(precision_value)),
+            ("consistency", parameters.consistency.map(consistency_value)),
+        ];
+
+        let mut pairs: Vec<(String, String)> = self.url.query_pairs()
+            .filter(|(name, _)| !set.iter().any(|(key, value)| value.is_some() && name == key))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+        pairs.extend(set.iter().filter_map(|(key, value)| value.map(|value| (key.to_string(), value.to_string()))));
+
+        if pairs.is_empty() {
+            self.url.set_query(None);
+        } else {
+            self.url.query_pairs_mut().clear().extend_pairs(pairs);
+        }
+    }
+
+    fn full(&self) -> bool {
+        self.lines >= self.batching.max_lines || self.batch.len() >= self.batching.max_bytes
+    }
+
+    /// Takes the batch to post it
+    fn take(&mut self) -> Pending {
+        Pending {
+            url: self.url.clone(),
+            timeout: self.timeout,
+            batch: std::mem::take(&mut self.batch),
+            lines: std::mem::replace(&mut self.lines, 0),
+            started: self.started.take(),
+        }
+    }
+
+    /// Records the response to the posted batch. A failed batch is put back in front of the
+    /// lines written meanwhile, to be posted again
+    fn finish(&mut self, pending: Pending, response: io::Result<Response>) -> io::Result<()> {
+        self.posting = false;
+        let err = match response {
+            Ok(response) if (200..300).contains(&response.status) => {
+                self.failures = 0;
+                self.retry_at = None;
+                return Ok(());
+            },
+            Ok(response) if (400..500).contains(&response.status) && !matches!(response.status, 408 | 429) => {
+                self.failures = 0;
+                self.retry_at = None;
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                    format!("The lines were rejected with status {}: {}", response.status, response.body)));
+            },
+            Ok(response) => io::Error::new(io::ErrorKind::Other,
+                format!("The write failed with status {}: {}", response.status, response.body)),
+            Err(err) => err
+        };
+
+        let mut batch = pending.batch;
+        batch.extend_from_slice(&self.batch);
+        self.batch = batch;
+        self.lines += pending.lines;
+        self.started = pending.started.or(self.started);
+        self.failures = self.failures.saturating_add(1);
+        self.retry_at = Some(Instant::now() + self.backoff.delay(self.failures));
+        Err(err)
+    }
+
+    /// When the flusher posts the batch, `None` if there is none or if it is being posted
+    fn due_at(&self) -> Option<Instant> {
+        if self.posting {
+            return None;
+        }
+        let due = self.started? + self.batching.max_delay;
+        Some(self.retry_at.map_or(due, |retry_at| retry_at.max(due)))
+    }
+
+    /// Whether a failed batch waits for its retry
+    fn waiting(&self) -> bool {
+        self.retry_at.map_or(false, |retry_at| Instant::now() < retry_at)
+    }
+}
+
+impl Pending {
+    fn post(&self) -> io::Result<Response> {
+        let mut stream = self.connect()?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        stream.set_read_timeout(Some(self.timeout))?;
+
+        let target = match self.url.query() {
+            Some(query) => format!("{}?{}", self.url.path(), query),
+            None => self.url.path().to_string()
+        };
+        let host = match self.url.port() {
+            Some(port) => format!("{}:{}", self.url.host_str().unwrap_or_default(), port),
+            None => self.url.host_str().unwrap_or_default().to_string()
+        };
+
+        let mut request = format!("POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+                                   Content-Length: {}\r\nConnection: close\r\n",
+                                  target, host, self.batch.len());
+        if !self.url.username().is_empty() {
+            // The URL keeps the credentials percent-encoded, e.g. `@` as `%40`
+            let mut credentials = percent_decode(self.url.username());
+            credentials.push(b':');
+            credentials.extend(percent_decode(self.url.password().unwrap_or_default()));
+            request.push_str(&format!("Authorization: Basic {}\r\n", base64(&credentials)));
+        }
+        request.push_str("\r\n");
+
+        let mut message = request.into_bytes();
+        message.extend_from_slice(&self.batch);
+        stream.write_all(&message)?;
+
+        // The connection is closed by the server after the response
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+        parse_response(&response)
+    }
+
+    fn connect(&self) -> io::Result<net::TcpStream> {
+        // Resolved on every attempt, like the addresses of TCP connections
+        let mut last_err = io::Error::new(io::ErrorKind::NotFound, "The host could not be resolved");
+        for addr in self.url.socket_addrs(|| None)? {
+            match net::TcpStream::connect_timeout(&addr, self.timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_err = err
+            }
+        }
+        Err(last_err)
+    }
+}
+
+fn precision_value(precision: Precision) -> &'static str {
+    match precision {
+        Precision::Nanoseconds => "ns",
+        Precision::Microseconds => "u",
+        Precision::Milliseconds => "ms",
+        Precision::Seconds => "s"
+    }
+}
+
+fn consistency_value(consistency: Consistency) -> &'static str {
+    match consistency {
+        Consistency::Any => "any",
+        Consistency::One => "one",
+        Consistency::Quorum => "quorum",
+        Consistency::All => "all"
+    }
+}
+
+/// Parses the status line and the body of a response, e.g. `HTTP/1.1 204 No Content`
+fn parse_response(response: &[u8]) -> io::Result<Response> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "The response is not a valid HTTP response");
+
+    let header_end = response.windows(4).position(|window| window == b"\r\n\r\n").ok_or_else(invalid)?;
+    let head = String::from_utf8_lossy(&response[..header_end]);
+    let status = head.lines().next()
+        .filter(|line| line.starts_with("HTTP/1."))
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(invalid)?;
+
+    // InfluxDB describes errors in the body, e.g. `{"error":"unable to parse ..."}`
+    let body = String::from_utf8_lossy(&response[header_end + 4..]).trim().to_string();
+    Ok(Response { status, body })
+}
+
+/// Decodes the `%XX` sequences of a part of a URL. Invalid sequences are kept as they are
+fn percent_decode(encoded: &str) -> Vec<u8> {
+    let bytes = encoded.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) if bytes[i] == b'%' => {
+                decoded.push(byte);
+                i += 3;
+            },
+            _ => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    decoded
+}
+
+/// Encodes the bytes as standard base64 with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let triple = chunk.iter().enumerate().fold(0u32, |triple, (i, &byte)| triple | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(triple >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Client, TelegrafDrainBuilder};
+    use slog::{Logger, Drain};
+    use std::thread;
+
+    /// Answers one request per status and returns the heads and bodies of the requests
+    fn stand_in(statuses: Vec<u16>) -> (String, thread::JoinHandle<Vec<(String, String)>>) {
+        slow_stand_in(statuses, Duration::from_millis(0))
+    }
+
+    /// Answers each request after the delay
+    fn slow_stand_in(statuses: Vec<u16>, delay: Duration) -> (String, thread::JoinHandle<Vec<(String, String)>>) {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || statuses.into_iter().map(|status| {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let (head, length) = loop {
+                let mut buf = [0; 1024];
+                let read = stream.read(&mut buf).unwrap();
+                assert!(read > 0);
+                request.extend_from_slice(&buf[..read]);
+                if let Some(end) = request.windows(4).position(|window| window == b"\r\n\r\n") {
+                    let head = String::from_utf8(request[..end].to_vec()).unwrap();
+                    let length: usize = head.lines()
+                        .find_map(|line| line.strip_prefix("Content-Length: "))
+                        .unwrap().parse().unwrap();
+                    request.drain(..end + 4);
+                    break (head, length);
+                }
+            };
+            while request.len() < length {
+                let mut buf = [0; 1024];
+                let read = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..read]);
+            }
+
+            thread::sleep(delay);
+            let body = if status == 400 { r#"{"error":"unable to parse 'm f='"}"# } else { "" };
+            write!(stream, "HTTP/1.1 {} Status\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                   status, body.len(), body).unwrap();
+            (head, String::from_utf8(request).unwrap())
+        }).collect());
+
+        (url, handle)
+    }
+
+    #[test]
+    fn test_http_write() {
+        let (url, stand_in) = stand_in(vec![204, 204]);
+        let url = url.replace("http://", "http://user:secret@") + "/write?db=ignored&u=admin";
+        let parameters = WriteParameters {
+            db: Some("logs".into()),
+            precision: Some(Precision::Milliseconds),
+            consistency: Some(Consistency::One),
+            ..WriteParameters::default()
+        };
+        let batching = Batching { max_lines: 2, ..Batching::default() };
+        let mut client = Client::new(url).unwrap().write_parameters(parameters).batching(batching);
+
+        client.write(b"m f=1i\n").unwrap();
+        client.write(b"m f=2i\n").unwrap();
+        client.write(b"m f=3i\n").unwrap();
+        client.flush().unwrap();
+        // Nothing is left to post
+        client.flush().unwrap();
+
+        let requests = stand_in.join().unwrap();
+        let (head, body) = &requests[0];
+        assert!(head.starts_with("POST /write?u=admin&db=logs&precision=ms&consistency=one HTTP/1.1\r\n"), "{}", head);
+        assert!(head.contains("\r\nAuthorization: Basic dXNlcjpzZWNyZXQ="), "{}", head);
+        assert_eq!(body, "m f=1i\nm f=2i\n");
+        assert_eq!(requests[1].1, "m f=3i\n");
+    }
+
+    #[test]
+    fn test_http_precision() {
+        // The precision of the drain replaces the one of the parameters
+        let (url, stand_in) = stand_in(vec![204]);
+        let parameters = WriteParameters { precision: Some(Precision::Milliseconds), ..WriteParameters::default() };
+        let client = Client::new(url + "/write?precision=u").unwrap().write_parameters(parameters);
+        let drain = TelegrafDrainBuilder::new(client, "m".into()).timestamp_precision(Precision::Seconds).build();
+        let log = Logger::root(Mutex::new(drain).fuse(), o!());
+        info!(log, "log"; "f" => 1);
+        drop(log);
+
+        let (head, body) = &stand_in.join().unwrap()[0];
+        assert!(head.starts_with("POST /write?precision=s HTTP/1.1\r\n"), "{}", head);
+        assert!(body.starts_with("m f=1i "), "{}", body);
+    }
+
+    #[test]
+    fn test_http_credentials() {
+        // Reserved characters of the credentials are decoded
+        let (url, stand_in) = stand_in(vec![204]);
+        let mut client = Client::new(url.replace("http://", "http://us%40er:p%3Ass@")).unwrap();
+        client.write(b"m f=1i\n").unwrap();
+        client.flush().unwrap();
+        let (head, _) = &stand_in.join().unwrap()[0];
+        assert!(head.contains(&format!("\r\nAuthorization: Basic {}", base64(b"us@er:p:ss"))), "{}", head);
+    }
+
+    #[test]
+    fn test_http_responses() {
+        let (url, stand_in) = stand_in(vec![400, 503, 204]);
+        let backoff = Backoff { initial: Duration::from_millis(300), jitter: 0.0, ..Backoff::default() };
+        let batching = Batching { max_lines: 1, max_bytes: 16, ..Batching::default() };
+        let mut client = Client::new(url).unwrap().backoff(backoff).batching(batching);
+
+        // Rejected lines are dropped
+        let err = client.write(b"m f=\n").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains("400: {\"error\":\"unable to parse 'm f='\"}"), "{}", err);
+
+        // Failed lines are kept and posted again after the delay, until the batch is full
+        assert_eq!(client.write(b"m f=1i\n").unwrap_err().kind(), io::ErrorKind::Other);
+        client.write(b"m f=2i\n").unwrap();
+        assert_eq!(client.write(b"m f=3i\n").unwrap_err().kind(), io::ErrorKind::NotConnected);
+        assert_eq!(client.flush().unwrap_err().kind(), io::ErrorKind::NotConnected);
+
+        thread::sleep(Duration::from_millis(300));
+        client.flush().unwrap();
+
+        let bodies: Vec<String> = stand_in.join().unwrap().into_iter().map(|(_, body)| body).collect();
+        assert_eq!(bodies, vec!["m f=\n", "m f=1i\n", "m f=1i\nm f=2i\n"]);
+    }
+
+    #[test]
+    fn test_http_max_delay() {
+        let (url, stand_in) = stand_in(vec![204, 204]);
+        let batching = Batching { max_delay: Duration::from_millis(100), ..Batching::default() };
+        let mut client = Client::new(url).unwrap().batching(batching);
+
+        // The batch is posted without further writes or flushes
+        client.write(b"m f=1i\n").unwrap();
+        thread::sleep(Duration::from_millis(300));
+
+        // The rest is posted when the client is dropped
+        client.write(b"m f=2i\n").unwrap();
+        drop(client);
+
+        let bodies: Vec<String> = stand_in.join().unwrap().into_iter().map(|(_, body)| body).collect();
+        assert_eq!(bodies, vec!["m f=1i\n", "m f=2i\n"]);
+    }
+
+    #[test]
+    fn test_http_write_while_posting() {
+        let (url, stand_in) = slow_stand_in(vec![204, 204], Duration::from_millis(500));
+        let batching = Batching { max_delay: Duration::from_millis(50), ..Batching::default() };
+        let mut client = Client::new(url).unwrap().batching(batching);
+
+        // Lines are added to the next batch while the flusher waits for the response
+        client.write(b"m f=1i\n").unwrap();
+        thread::sleep(Duration::from_millis(150));
+        let start = Instant::now();
+        client.write(b"m f=2i\n").unwrap();
+        assert!(start.elapsed() < Duration::from_millis(200));
+        drop(client);
+
+        let bodies: Vec<String> = stand_in.join().unwrap().into_iter().map(|(_, body)| body).collect();
+        assert_eq!(bodies, vec!["m f=1i\n", "m f=2i\n"]);
+    }
+
+    #[test]
+    fn test_parse_response() {
+        let response = parse_response(b"HTTP/1.1 204 No Content\r\nX-Influxdb-Version: 1.8.10\r\n\r\n").unwrap();
+        assert_eq!((response.status, response.body.as_str()), (204, ""));
+
+        let response = parse_response(b"HTTP/1.0 500 Internal Server Error\r\n\r\n{\"error\":\"timeout\"}\n").unwrap();
+        assert_eq!((response.status, response.body.as_str()), (500, "{\"error\":\"timeout\"}"));
+
+        assert!(parse_response(b"HTTP/1.1 204 No Content\r\n").is_err());
+        assert!(parse_response(b"SSH-2.0-OpenSSH\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"user:secret"), "dXNlcjpzZWNyZXQ=");
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("us%40er"), b"us@er");
+        assert_eq!(percent_decode("p%3Ass%2f%C3%A4"), "p:ss/ä".as_bytes());
+        assert_eq!(percent_decode("100%"), b"100%");
+        assert_eq!(percent_decode("%zz%4"), b"%zz%4");
+    }
+}
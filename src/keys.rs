@@ -0,0 +1,329 @@
+use slog::{Key, KV, OwnedKVList, Record};
+use crate::error::LineError;
+
+/// How values are handled whose key is used more than once within a record
+///
+/// Keys may conflict between the builder tags, the logger values and the record values, e.g.
+/// a logger value that is written as tag and a record value with the same key. InfluxDB cannot
+/// store a tag and a field with the same name. Within the same source, the first value serialized
+/// is kept, which slog ensures to be the newest one.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyConflicts {
+    /// Writes all values, regardless of conflicts
+    #[default]
+    Allow,
+    /// Record values replace logger values, which replace builder values
+    RecordWins,
+    /// Logger values replace builder values, which replace record values
+    LoggerWins,
+    /// Writes all values, but appends the suffix to the keys replaced under `RecordWins`
+    Rename(&'static str),
+    /// Fails the record
+    Reject,
+}
+
+/// How the case of keys is normalized, see `KeyMapping`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyCase {
+    /// Keeps the keys as they are
+    #[default]
+    Preserve,
+    /// Lowercases the keys, e.g. `userId` becomes `userid`
+    Lowercase,
+    /// Converts the keys to snake case, e.g. `userId`, `user-id` and `user id` become `user_id`
+    Snake,
+}
+
+/// How keys are renamed before they are written
+///
+/// Key conflicts are resolved between the mapped names, so keys that only conflict after the
+/// mapping, e.g. `userId` and `user_id`, are detected as well.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyMapping {
+    /// Keys that are written under another name, e.g. `("userId", "user")`. The names are
+    /// written as given, without normalization
+    pub renames: Vec<(&'static str, &'static str)>,
+    pub case: KeyCase,
+    /// Replaces all characters of a key except ASCII letters, digits, `_`, `-` and `.` with the
+    /// given character
+    pub replace_invalid: Option<char>,
+    /// The key of a logger value that sets the namespace of a child logger, e.g.
+    /// `log.new(o!("namespace" => "db"))`. The newest namespace is prepended to the keys of the
+    /// record values, the key itself is not written
+    pub namespace_key: Option<&'static str>,
+    /// Separates the namespace from the key, defaults to `.`
+    pub namespace_separator: &'static str,
+}
+
+impl KeyMapping {
+    pub(crate) const DEFAULT: KeyMapping = KeyMapping {
+        renames: Vec::new(),
+        case: KeyCase::Preserve,
+        replace_invalid: None,
+        namespace_key: None,
+        namespace_separator: ".",
+    };
+
+    /// Returns the name `key` is written as, using `buffer` if the name differs from the key
+    pub(crate) fn map<'b>(&self, key: &'b str, namespace: Option<&str>, buffer: &'b mut String) -> &'b str {
+        let rename = self.renames.iter().find(|&&(from, _)| from == key).map(|&(_, to)| to);
+        if rename.is_none() && namespace.is_none() && self.case == KeyCase::Preserve && self.replace_invalid.is_none() {
+            return key;
+        }
+
+        buffer.clear();
+        if let Some(namespace) = namespace {
+            buffer.push_str(namespace);
+            buffer.push_str(self.namespace_separator);
+        }
+        if let Some(rename) = rename {
+            buffer.push_str(rename);
+            return buffer;
+        }
+
+        let start = buffer.len();
+        match self.case {
+            KeyCase::Preserve => buffer.push_str(key),
+            KeyCase::Lowercase => buffer.extend(key.chars().flat_map(char::to_lowercase)),
+            KeyCase::Snake => push_snake_case(buffer, key)
+        }
+
+        if let Some(replacement) = self.replace_invalid {
+            let normalized: String = buffer[start..].chars()
+                .map(|c| if c.is_ascii_alphanumeric() || "_-.".contains(c) { c } else { replacement })
+                .collect();
+            buffer.truncate(start);
+            buffer.push_str(&normalized);
+        }
+
+        buffer
+    }
+}
+
+impl Default for KeyMapping {
+    fn default() -> Self {
+        KeyMapping::DEFAULT
+    }
+}
+
+/// Appends `key` converted to snake case
+fn push_snake_case(out: &mut String, key: &str) {
+    let mut prev: Option<char> = None;
+    let mut chars = key.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '-' || c.is_whitespace() {
+            if prev.is_some() && prev != Some('_') {
+                out.push('_');
+                prev = Some('_');
+            }
+            continue;
+        }
+
+        if c.is_uppercase() {
+            // Starts a new word after a lowercase letter or digit, or as last capital of an
+            // acronym followed by a word, e.g. `HTTPStatus`
+            let boundary = prev.map_or(false, |prev| prev.is_lowercase() || prev.is_ascii_digit()
+                || (prev.is_uppercase() && chars.peek().map_or(false, |next| next.is_lowercase())));
+            if boundary {
+                out.push('_');
+            }
+            out.extend(c.to_lowercase());
+        } else {
+            out.push(c);
+        }
+        prev = Some(c);
+    }
+}
+
+/// The origin of a value
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Values added to the `TelegrafDrainBuilder`
+    Builder = 0,
+    /// Values of the logger
+    Logger = 1,
+    /// Values of the logging statement
+    Record = 2,
+}
+
+const SOURCES: [Source; 3] = [Source::Builder, Source::Logger, Source::Record];
+
+/// Names stored in one buffer, which is reused for the next record
+#[derive(Default)]
+struct Names {
+    buffer: String,
+    /// The end of each name within the buffer
+    ends: Vec<usize>,
+}
+
+impl Names {
+    fn push(&mut self, name: &str) {
+        self.buffer.push_str(name);
+        self.ends.push(self.buffer.len());
+    }
+
+    fn contains(&self, name: &str) -> bool {
+        let mut start = 0;
+        self.ends.iter().any(|&end| {
+            let found = &self.buffer[start..end] == name;
+            start = end;
+            found
+        })
+    }
+
+    fn clear(&mut self) {
+        self.buffer.clear();
+        self.ends.clear();
+    }
+}
+
+/// Resolves key conflicts within one record according to a `KeyConflicts` policy
+///
+/// Conflicts are detected between the names written, i.e. after the `KeyMapping`. The resolver
+/// keeps its buffers between records.
+#[derive(Default)]
+pub(crate) struct KeyResolver {
+    policy: KeyConflicts,
+    /// The names of all values of each source
+    names: [Names; 3],
+    /// The names written under their own name so far
+    written: Names,
+    /// Buffer for renamed names
+    name: String,
+    /// Buffer for mapped keys
+    buffer: String,
+}
+
+impl KeyResolver {
+    pub fn new(policy: KeyConflicts) -> Self {
+        KeyResolver { policy, ..Default::default() }
+    }
+
+    /// Starts a record by collecting the names of all its sources. The namespace applies to
+    /// record values
+    pub fn start(&mut self, mapping: &KeyMapping, namespace: Option<&str>, rinfo: &Record,
+                 builder_values: &[OwnedKVList], logger_values: &OwnedKVList) -> slog::Result
+    {
+        self.names.iter_mut().for_each(Names::clear);
+        self.written.clear();
+
+        for kv in builder_values {
+            self.collect(Source::Builder, mapping, None, rinfo, kv)?;
+        }
+        self.collect(Source::Logger, mapping, None, rinfo, logger_values)?;
+        self.collect(Source::Record, mapping, namespace, rinfo, &rinfo.kv())
+    }
+
+    /// Collects the names of further values of the source
+    pub fn collect(&mut self, source: Source, mapping: &KeyMapping, namespace: Option<&str>, rinfo: &Record,
+                   values: &dyn KV) -> slog::Result
+    {
+        let mut collector = NameCollector {
+            names: &mut self.names[source as usize],
+            buffer: &mut self.buffer,
+            mapping,
+            namespace
+        };
+        values.serialize(rinfo, &mut collector)
+    }
+
+    fn priority(&self, source: Source) -> u8 {
+        match (self.policy, source) {
+            (KeyConflicts::LoggerWins, Source::Logger) => 2,
+            (KeyConflicts::LoggerWins, Source::Builder) => 1,
+            (KeyConflicts::LoggerWins, Source::Record) => 0,
+            (_, source) => source as u8
+        }
+    }
+
+    /// Returns what to write the already mapped name of a value from `source` as, or `None` if
+    /// the value is dropped
+    pub fn resolve<'n>(&'n mut self, name: &'n str, source: Source) -> Result<Option<&'n str>, slog::Error> {
+        let priority = self.priority(source);
+        let replaced = SOURCES.iter()
+            .any(|&source| self.priority(source) > priority && self.names[source as usize].contains(name));
+
+        if !replaced {
+            if self.written.contains(name) {
+                // Duplicate name within the same source
+                return Ok(None);
+            }
+            self.written.push(name);
+            return Ok(Some(name));
+        }
+
+        match self.policy {
+            KeyConflicts::Allow => Ok(Some(name)),
+            KeyConflicts::RecordWins | KeyConflicts::LoggerWins => Ok(None),
+            KeyConflicts::Rename(suffix) => {
+                self.name.clear();
+                self.name.push_str(name);
+                self.name.push_str(suffix);
+                Ok(Some(&self.name))
+            },
+            KeyConflicts::Reject => Err(LineError::KeyConflict(name.to_string()).into())
+        }
+    }
+}
+
+/// Collects the mapped names of all values serialized
+struct NameCollector<'a> {
+    names: &'a mut Names,
+    buffer: &'a mut String,
+    mapping: &'a KeyMapping,
+    namespace: Option<&'a str>,
+}
+
+impl<'a> slog::Serializer for NameCollector<'a> {
+    fn emit_arguments(&mut self, key: Key, _val: &std::fmt::Arguments) -> slog::Result {
+        let name = self.mapping.map(key, self.namespace, self.buffer);
+        self.names.push(name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_key_mapping() {
+        let map = |mapping: &KeyMapping, key, namespace| mapping.map(key, namespace, &mut String::new()).to_string();
+
+        let snake = KeyMapping { case: KeyCase::Snake, ..Default::default() };
+        for key in &["userId", "user-id", "user id", "user_id", "UserId", "user  -id"] {
+            assert_eq!(map(&snake, key, None), "user_id");
+        }
+        assert_eq!(map(&snake, "HTTPStatus", None), "http_status");
+        assert_eq!(map(&snake, "status2Code", None), "status2_code");
+        assert_eq!(map(&snake, "http.statusCode", None), "http.status_code");
+
+        let lowercase = KeyMapping { case: KeyCase::Lowercase, ..Default::default() };
+        assert_eq!(map(&lowercase, "userId", None), "userid");
+
+        let mapping = KeyMapping {
+            renames: vec![("userId", "User")],
+            case: KeyCase::Snake,
+            replace_invalid: Some('_'),
+            ..Default::default()
+        };
+        assert_eq!(map(&mapping, "userId", None), "User");
+        assert_eq!(map(&mapping, "userId", Some("db")), "db.User");
+        assert_eq!(map(&mapping, "a,b=c\"d", Some("db")), "db.a_b_c_d");
+        assert_eq!(map(&KeyMapping::default(), "user id", None), "user id");
+    }
+
+    #[test]
+    fn test_names() {
+        let mut names = Names::default();
+        names.push("ab");
+        names.push("c");
+        assert!(names.contains("ab") && names.contains("c"));
+        assert!(!names.contains("a") && !names.contains("abc") && !names.contains(""));
+
+        names.clear();
+        assert!(!names.contains("ab"));
+        names.push("");
+        assert!(names.contains(""));
+    }
+}
@@ -6,7 +6,9 @@
 //!use slog_telegraf::{TelegrafDrain};
 //!
 //!fn main() {
-//!    let drain = TelegrafDrain::new("tcp://127.0.0.1:8094".into(), "measurement".into()).unwrap().fuse();
+//!    // Writes fail while Telegraf is unavailable, e.g. while it restarts. Such records are dropped
+//!    // instead of panicking like `fuse` would
+//!    let drain = TelegrafDrain::new("tcp://127.0.0.1:8094".into(), "measurement".into()).unwrap().ignore_res();
 //!    let drain = slog_async::Async::new(drain).build().fuse();
 //!
 //!    let log = Logger::root(drain, o!("ver" => "1.2.1"));
@@ -20,12 +22,30 @@
 extern crate slog;
 extern crate url;
 
+mod cardinality;
+mod defaults;
 mod drain;
 mod error;
+mod http;
+mod keys;
+#[cfg(feature = "nested-values")]
+mod nested;
+mod routing;
 mod ser;
 mod telegraf;
+mod validate;
+mod values;
 
-pub use drain::{TelegrafDrain, TelegrafDrainBuilder};
-pub use error::Error;
-pub use telegraf::{Client};
-pub use ser::TelegrafSocketSerializer;
+pub use cardinality::{CardinalityGuard, CardinalityOverflow, CardinalityCallback};
+pub use defaults::DefaultValue;
+pub use drain::{TelegrafDrain, TelegrafDrainBuilder, EmptyFields};
+pub use error::{Error, LineError};
+pub use http::{WriteParameters, Consistency, Batching};
+pub use keys::{KeyConflicts, KeyMapping, KeyCase, Source};
+#[cfg(feature = "nested-values")]
+pub use nested::{NestedValues, Sequences};
+pub use routing::{Routing, RoutingPredicate, Placement};
+pub use telegraf::{Client, Backoff, DroppedDatagrams};
+pub use values::{Tag, Field, Unsigned, Timestamp, Measurement};
+pub use ser::{TelegrafSocketSerializer, SerializerConfig, UnsignedIntegers, Precision};
+pub use ser::{NonFiniteFloats, NonFiniteFloatCounters, ValueEncoding, MissingValue, Booleans, LengthLimits, ErrorValues};
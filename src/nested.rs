@@ -0,0 +1,720 @@
+use std::fmt::{self, Display, Write};
+use std::convert::TryFrom;
+use std::io;
+use serde::ser::{self, Impossible, Serialize};
+use slog::SerdeValue;
+
+/// How sequences, e.g. `Vec`s and tuples, inside nested values are written
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Sequences {
+    /// Writes the elements with their index as key, e.g. `ports.0=80i,ports.1=443i`
+    #[default]
+    Index,
+    /// Joins the elements with the separator into a string, e.g. `ports="80,443"`. Elements that
+    /// are not scalars are omitted, as are sequences without scalars
+    Join(&'static str),
+    /// Omits sequences
+    Drop,
+}
+
+/// How nested values, i.e. values passed as `slog::Serde`, are flattened into dotted keys, e.g.
+/// `http.status=200i`
+///
+/// Values nested deeper than `max_depth` and values beyond the first `max_keys` of a nested
+/// value are dropped, which adds the marker field of `LengthLimits`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NestedValues {
+    /// The number of keys added to the key of the value, defaults to 4
+    pub max_depth: usize,
+    /// The number of tags or fields written per nested value, defaults to 64
+    pub max_keys: usize,
+    pub sequences: Sequences,
+    /// Separates the keys, defaults to `.`
+    pub separator: &'static str,
+}
+
+impl NestedValues {
+    pub(crate) const DEFAULT: NestedValues = NestedValues {
+        max_depth: 4,
+        max_keys: 64,
+        sequences: Sequences::Index,
+        separator: ".",
+    };
+}
+
+impl Default for NestedValues {
+    fn default() -> Self {
+        NestedValues::DEFAULT
+    }
+}
+
+/// A scalar of a nested value
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Leaf<'v> {
+    Bool(bool),
+    Int(i64),
+    Unsigned(u64),
+    Float(f64),
+    Str(&'v str),
+    None,
+    Unit,
+}
+
+/// Calls `write` with the name and value of every scalar of the nested value. The names start
+/// with `name`. Returns whether scalars were dropped because of the limits
+pub(crate) fn flatten<F>(value: &dyn SerdeValue, name: &str, config: &NestedValues, write: F)
+    -> Result<bool, slog::Error>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    let mut flattener = Flattener { path: name.to_owned(), depth: 0, keys: 0, config, write, dropped: false };
+    match value.as_serde().serialize(&mut flattener) {
+        Ok(()) => Ok(flattener.dropped),
+        Err(FlattenError::Slog(err)) => Err(err),
+        Err(FlattenError::Custom(msg)) => Err(io::Error::new(io::ErrorKind::Other, msg).into())
+    }
+}
+
+#[derive(Debug)]
+enum FlattenError {
+    /// Writing a scalar failed
+    Slog(slog::Error),
+    /// Serializing the value failed
+    Custom(String),
+}
+
+impl Display for FlattenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FlattenError::Slog(err) => write!(f, "{}", err),
+            FlattenError::Custom(msg) => f.write_str(msg)
+        }
+    }
+}
+
+impl std::error::Error for FlattenError {}
+
+impl ser::Error for FlattenError {
+    fn custom<T: Display>(msg: T) -> Self {
+        FlattenError::Custom(msg.to_string())
+    }
+}
+
+struct Flattener<'c, F> {
+    /// The name of the current value
+    path: String,
+    depth: usize,
+    /// The number of scalars written so far
+    keys: usize,
+    config: &'c NestedValues,
+    write: F,
+    dropped: bool,
+}
+
+impl<'c, F> Flattener<'c, F>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    fn leaf(&mut self, leaf: Leaf) -> Result<(), FlattenError> {
+        if self.keys == self.config.max_keys {
+            self.dropped = true;
+            return Ok(());
+        }
+        self.keys += 1;
+        (self.write)(&self.path, leaf).map_err(FlattenError::Slog)
+    }
+
+    /// Appends the key to the path and returns the previous length of the path, or `None` if the
+    /// value is nested too deep
+    fn enter(&mut self, key: impl Display) -> Result<Option<usize>, FlattenError> {
+        if self.depth == self.config.max_depth {
+            self.dropped = true;
+            return Ok(None);
+        }
+
+        let len = self.path.len();
+        self.path.push_str(self.config.separator);
+        write!(self.path, "{}", key).map_err(ser::Error::custom)?;
+        self.depth += 1;
+        Ok(Some(len))
+    }
+
+    fn leave(&mut self, len: Option<usize>) {
+        if let Some(len) = len {
+            self.path.truncate(len);
+            self.depth -= 1;
+        }
+    }
+
+    fn child<T: Serialize + ?Sized>(&mut self, key: impl Display, value: &T) -> Result<(), FlattenError> {
+        let len = match self.enter(key)? {
+            Some(len) => len,
+            None => return Ok(())
+        };
+        let result = value.serialize(&mut *self);
+        self.leave(Some(len));
+        result
+    }
+
+    /// Starts a sequence, a map or a struct, optionally below the key of an enum variant
+    fn compound(&mut self, variant: Option<&'static str>, sequence: bool) -> Result<Compound<'_, 'c, F>, FlattenError> {
+        let (variant, skip) = match variant {
+            Some(variant) => match self.enter(variant)? {
+                Some(len) => (Some(len), false),
+                None => (None, true)
+            },
+            None => (None, false)
+        };
+
+        let (skip, joined) = match self.config.sequences {
+            _ if !sequence || skip => (skip, None),
+            Sequences::Index => (false, None),
+            Sequences::Join(_) => (false, Some(Vec::new())),
+            Sequences::Drop => (true, None)
+        };
+
+        Ok(Compound { flattener: self, index: 0, key: None, variant, skip, joined })
+    }
+}
+
+impl<'a, 'c, F> ser::Serializer for &'a mut Flattener<'c, F>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    type Ok = ();
+    type Error = FlattenError;
+    type SerializeSeq = Compound<'a, 'c, F>;
+    type SerializeTuple = Compound<'a, 'c, F>;
+    type SerializeTupleStruct = Compound<'a, 'c, F>;
+    type SerializeTupleVariant = Compound<'a, 'c, F>;
+    type SerializeMap = Compound<'a, 'c, F>;
+    type SerializeStruct = Compound<'a, 'c, F>;
+    type SerializeStructVariant = Compound<'a, 'c, F>;
+
+    fn serialize_bool(self, v: bool) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Int(v as i64))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Int(v as i64))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Int(v as i64))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Int(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<(), FlattenError> {
+        match (i64::try_from(v), u64::try_from(v)) {
+            (Ok(v), _) => self.leaf(Leaf::Int(v)),
+            (_, Ok(v)) => self.leaf(Leaf::Unsigned(v)),
+            _ => self.leaf(Leaf::Str(&v.to_string()))
+        }
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Int(v as i64))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Int(v as i64))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Int(v as i64))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Unsigned(v))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<(), FlattenError> {
+        match u64::try_from(v) {
+            Ok(v) => self.leaf(Leaf::Unsigned(v)),
+            Err(_) => self.leaf(Leaf::Str(&v.to_string()))
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Float(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Float(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Str(v.encode_utf8(&mut [0; 4])))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Str(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<(), FlattenError> {
+        use serde::ser::SerializeSeq;
+
+        let mut seq = self.serialize_seq(Some(v.len()))?;
+        for byte in v {
+            seq.serialize_element(byte)?;
+        }
+        seq.end()
+    }
+
+    fn serialize_none(self) -> Result<(), FlattenError> {
+        self.leaf(Leaf::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<(), FlattenError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Unit)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<(), FlattenError> {
+        self.leaf(Leaf::Unit)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str)
+        -> Result<(), FlattenError>
+    {
+        self.leaf(Leaf::Str(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T)
+        -> Result<(), FlattenError>
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32,
+                                                        variant: &'static str, value: &T)
+        -> Result<(), FlattenError>
+    {
+        self.child(variant, value)
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, FlattenError> {
+        self.compound(None, true)
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, FlattenError> {
+        self.compound(None, true)
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize)
+        -> Result<Self::SerializeTupleStruct, FlattenError>
+    {
+        self.compound(None, true)
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize)
+        -> Result<Self::SerializeTupleVariant, FlattenError>
+    {
+        self.compound(Some(variant), true)
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, FlattenError> {
+        self.compound(None, false)
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, FlattenError> {
+        self.compound(None, false)
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str, _len: usize)
+        -> Result<Self::SerializeStructVariant, FlattenError>
+    {
+        self.compound(Some(variant), false)
+    }
+}
+
+/// The state of a sequence, map or struct being flattened
+struct Compound<'a, 'c, F> {
+    flattener: &'a mut Flattener<'c, F>,
+    /// The index of the next element of a sequence
+    index: usize,
+    /// The key of the next value of a map, `None` if it is not a scalar
+    key: Option<String>,
+    /// The length of the path before the key of the enum variant was appended
+    variant: Option<usize>,
+    /// Whether the elements are omitted
+    skip: bool,
+    /// The scalar elements of a sequence, joined when it is finished, see `Sequences::Join`
+    joined: Option<Vec<String>>,
+}
+
+impl<'a, 'c, F> Compound<'a, 'c, F>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    fn element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        let index = self.index;
+        self.index += 1;
+        if self.skip {
+            return Ok(());
+        }
+
+        match &mut self.joined {
+            Some(joined) => {
+                if let Ok(scalar) = value.serialize(ScalarFormatter) {
+                    joined.push(scalar);
+                }
+                Ok(())
+            },
+            None => self.flattener.child(index, value)
+        }
+    }
+
+    fn field<T: Serialize + ?Sized>(&mut self, key: impl Display, value: &T) -> Result<(), FlattenError> {
+        if self.skip {
+            return Ok(());
+        }
+        self.flattener.child(key, value)
+    }
+
+    fn finish(self) -> Result<(), FlattenError> {
+        // Sequences without scalars are omitted, since they would be written as empty tag value
+        let result = match (&self.joined, self.flattener.config.sequences) {
+            (Some(joined), Sequences::Join(separator)) if !joined.is_empty() =>
+                self.flattener.leaf(Leaf::Str(&joined.join(separator))),
+            _ => Ok(())
+        };
+        self.flattener.leave(self.variant);
+        result
+    }
+}
+
+impl<'a, 'c, F> ser::SerializeSeq for Compound<'a, 'c, F>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    type Ok = ();
+    type Error = FlattenError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), FlattenError> {
+        self.finish()
+    }
+}
+
+impl<'a, 'c, F> ser::SerializeTuple for Compound<'a, 'c, F>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    type Ok = ();
+    type Error = FlattenError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), FlattenError> {
+        self.finish()
+    }
+}
+
+impl<'a, 'c, F> ser::SerializeTupleStruct for Compound<'a, 'c, F>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    type Ok = ();
+    type Error = FlattenError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), FlattenError> {
+        self.finish()
+    }
+}
+
+impl<'a, 'c, F> ser::SerializeTupleVariant for Compound<'a, 'c, F>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    type Ok = ();
+    type Error = FlattenError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        self.element(value)
+    }
+
+    fn end(self) -> Result<(), FlattenError> {
+        self.finish()
+    }
+}
+
+impl<'a, 'c, F> ser::SerializeMap for Compound<'a, 'c, F>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    type Ok = ();
+    type Error = FlattenError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), FlattenError> {
+        self.key = key.serialize(ScalarFormatter).ok();
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), FlattenError> {
+        match self.key.take() {
+            Some(key) => self.field(key, value),
+            // Keys that are not scalars can not be written
+            None => Ok(())
+        }
+    }
+
+    fn end(self) -> Result<(), FlattenError> {
+        self.finish()
+    }
+}
+
+impl<'a, 'c, F> ser::SerializeStruct for Compound<'a, 'c, F>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    type Ok = ();
+    type Error = FlattenError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T)
+        -> Result<(), FlattenError>
+    {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), FlattenError> {
+        self.finish()
+    }
+}
+
+impl<'a, 'c, F> ser::SerializeStructVariant for Compound<'a, 'c, F>
+    where F: FnMut(&str, Leaf) -> slog::Result
+{
+    type Ok = ();
+    type Error = FlattenError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T)
+        -> Result<(), FlattenError>
+    {
+        self.field(key, value)
+    }
+
+    fn end(self) -> Result<(), FlattenError> {
+        self.finish()
+    }
+}
+
+/// Formats scalars, used for map keys and joined sequences. Fails for everything else
+struct ScalarFormatter;
+
+impl ScalarFormatter {
+    fn not_scalar() -> FlattenError {
+        FlattenError::Custom("not a scalar".to_owned())
+    }
+}
+
+impl ser::Serializer for ScalarFormatter {
+    type Ok = String;
+    type Error = FlattenError;
+    type SerializeSeq = Impossible<String, FlattenError>;
+    type SerializeTuple = Impossible<String, FlattenError>;
+    type SerializeTupleStruct = Impossible<String, FlattenError>;
+    type SerializeTupleVariant = Impossible<String, FlattenError>;
+    type SerializeMap = Impossible<String, FlattenError>;
+    type SerializeStruct = Impossible<String, FlattenError>;
+    type SerializeStructVariant = Impossible<String, FlattenError>;
+
+    fn serialize_bool(self, v: bool) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_char(self, v: char) -> Result<String, FlattenError> {
+        Ok(v.to_string())
+    }
+
+    fn serialize_str(self, v: &str) -> Result<String, FlattenError> {
+        Ok(v.to_owned())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<String, FlattenError> {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_none(self) -> Result<String, FlattenError> {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<String, FlattenError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<String, FlattenError> {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<String, FlattenError> {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str)
+        -> Result<String, FlattenError>
+    {
+        Ok(variant.to_owned())
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T)
+        -> Result<String, FlattenError>
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(self, _name: &'static str, _index: u32,
+                                                        _variant: &'static str, _value: &T)
+        -> Result<String, FlattenError>
+    {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, FlattenError> {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, FlattenError> {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, _len: usize)
+        -> Result<Self::SerializeTupleStruct, FlattenError>
+    {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize)
+        -> Result<Self::SerializeTupleVariant, FlattenError>
+    {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, FlattenError> {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_struct(self, _name: &'static str, _len: usize) -> Result<Self::SerializeStruct, FlattenError> {
+        Err(ScalarFormatter::not_scalar())
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, _variant: &'static str, _len: usize)
+        -> Result<Self::SerializeStructVariant, FlattenError>
+    {
+        Err(ScalarFormatter::not_scalar())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn flattened<T>(value: T, config: &NestedValues) -> (Vec<String>, bool)
+        where T: Serialize + Clone + Send + 'static
+    {
+        let mut leaves = Vec::new();
+        let dropped = flatten(&slog::Serde(value), "v", config, |name, leaf| {
+            leaves.push(format!("{}={:?}", name, leaf));
+            Ok(())
+        }).unwrap();
+        (leaves, dropped)
+    }
+
+    #[test]
+    fn test_flatten() {
+        let config = NestedValues::default();
+        assert_eq!(flattened(42u8, &config), (vec!["v=Int(42)".to_owned()], false));
+
+        let mut http = BTreeMap::new();
+        http.insert("method", vec!["GET"]);
+        http.insert("status", vec![]);
+        let mut map = BTreeMap::new();
+        map.insert("http", http);
+        let (leaves, _) = flattened((map.clone(), Some(1.5), None::<u8>, ()), &config);
+        assert_eq!(leaves, vec!["v.0.http.method.0=Str(\"GET\")", "v.1=Float(1.5)", "v.2=None", "v.3=Unit"]);
+
+        let joined = NestedValues { sequences: Sequences::Join(","), separator: "_", ..config };
+        assert_eq!(flattened(vec![80u16, 443], &joined).0, vec!["v=Str(\"80,443\")"]);
+        assert_eq!(flattened((vec![1u8], 2u8, "", 3u8), &joined).0, vec!["v=Str(\"2,,3\")"]);
+        assert_eq!(flattened(vec![vec![1u8]], &joined).0, Vec::<String>::new());
+        assert_eq!(flattened(map, &joined).0, vec!["v_http_method=Str(\"GET\")"]);
+
+        let dropped = NestedValues { sequences: Sequences::Drop, ..config };
+        assert_eq!(flattened((1u8, 2u8), &dropped), (vec![], false));
+    }
+
+    #[test]
+    fn test_flatten_limits() {
+        let shallow = NestedValues { max_depth: 2, ..NestedValues::default() };
+        let (leaves, dropped) = flattened((1u8, (2u8, (3u8, 4u8))), &shallow);
+        assert_eq!(leaves, vec!["v.0=Int(1)", "v.1.0=Int(2)"]);
+        assert!(dropped);
+
+        let few = NestedValues { max_keys: 2, ..NestedValues::default() };
+        let (leaves, dropped) = flattened(vec![1u64, 2, 3], &few);
+        assert_eq!(leaves, vec!["v.0=Unsigned(1)", "v.1=Unsigned(2)"]);
+        assert!(dropped);
+
+        let (leaves, dropped) = flattened(vec![1u64, 2], &few);
+        assert_eq!(leaves.len(), 2);
+        assert!(!dropped);
+    }
+}
@@ -0,0 +1,84 @@
+use crate::cardinality::CardinalityGuard;
+use crate::keys::Source;
+
+/// Whether a value is written as tag or as field
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Placement {
+    Tag,
+    Field,
+}
+
+/// Closure of `Routing::Predicate`, called with the key and source of a value
+pub type RoutingPredicate = Box<dyn Fn(&str, Source) -> Placement + Send + Sync>;
+
+/// Decides for each value whether it is written as tag or as field
+///
+/// Every tag value creates a series in InfluxDB, so values with many distinct values, e.g.
+/// request ids, should be written as fields.
+#[derive(Default)]
+pub enum Routing {
+    /// Writes the builder and logger values as tags and the record values as fields
+    #[default]
+    Default,
+    /// Writes the builder values as tags and the logger and record values as fields
+    LoggerValuesAsFields,
+    /// Writes the values with the listed keys as tags and all other values as fields,
+    /// regardless of their source
+    TagAllowlist(Vec<&'static str>),
+    /// Writes the values with the listed keys as fields and all other values like `Default`
+    TagDenylist(Vec<&'static str>),
+    /// Calls the closure with the key and source of each value
+    Predicate(RoutingPredicate),
+}
+
+impl Routing {
+    /// Creates a `Routing::Predicate` from the closure
+    pub fn predicate<F>(predicate: F) -> Self
+        where F: Fn(&str, Source) -> Placement + Send + Sync + 'static
+    {
+        Routing::Predicate(Box::new(predicate))
+    }
+
+    pub(crate) fn route(&self, key: &str, source: Source) -> Placement {
+        match self {
+            Routing::Default => default_placement(source),
+            Routing::LoggerValuesAsFields => match source {
+                Source::Builder => Placement::Tag,
+                Source::Logger | Source::Record => Placement::Field
+            },
+            Routing::TagAllowlist(keys) if keys.contains(&key) => Placement::Tag,
+            Routing::TagAllowlist(_) => Placement::Field,
+            Routing::TagDenylist(keys) if keys.contains(&key) => Placement::Field,
+            Routing::TagDenylist(_) => default_placement(source),
+            Routing::Predicate(predicate) => predicate(key, source)
+        }
+    }
+}
+
+fn default_placement(source: Source) -> Placement {
+    match source {
+        Source::Builder | Source::Logger => Placement::Tag,
+        Source::Record => Placement::Field
+    }
+}
+
+/// The routing of a drain, together with the keys that are not written at all
+#[derive(Default)]
+pub(crate) struct Router {
+    pub routing: Routing,
+    /// The key that sets the measurement, see `TelegrafDrainBuilder::measurement_key`
+    pub measurement_key: Option<&'static str>,
+    /// The key that sets the namespace, see `KeyMapping::namespace_key`
+    pub namespace_key: Option<&'static str>,
+    pub cardinality: Option<CardinalityGuard>,
+}
+
+impl Router {
+    /// Returns where the value is placed, or `None` if it is not written
+    pub fn route(&self, key: &str, source: Source) -> Option<Placement> {
+        if self.measurement_key == Some(key) || self.namespace_key == Some(key) {
+            return None;
+        }
+        Some(self.routing.route(key, source))
+    }
+}
@@ -1,54 +1,1176 @@
 use std::fmt;
 use std::fmt::Write;
+use std::error::Error as StdError;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
 use slog::{Key};
+use crate::cardinality::{CardinalityGuard, Checked};
+use crate::keys::{KeyMapping, KeyResolver, Source};
+use crate::routing::{Placement, Router};
+use crate::values::Directives;
+use crate::error::LineError;
+#[cfg(feature = "nested-values")]
+use crate::nested::{self, Leaf, NestedValues};
+
+/// How unsigned integer fields are written
+///
+/// Except for `Native`, only `u64` and `usize` values are affected, since all other unsigned
+/// integers always fit into the signed 64 bit integers of the line protocol.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UnsignedIntegers {
+    /// Writes all unsigned integers with the `u` suffix, e.g. `value=10u`. Requires InfluxDB 1.8+ or 2.x
+    Native,
+    /// Writes values bigger than `i64::MAX` as `i64::MAX`
+    #[default]
+    Saturate,
+    /// Writes `u64` and `usize` values as floats. This may lose precision
+    Float,
+    /// Writes `u64` and `usize` values as strings, e.g. `value="10"`
+    String,
+}
+
+/// How `NaN` and infinite float fields are written, since InfluxDB rejects lines containing them
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum NonFiniteFloats {
+    /// Omits the field
+    #[default]
+    Drop,
+    /// Writes the given finite value instead, e.g. `value=0`
+    Replace(f64),
+    /// Writes the value as string field, i.e. `value="NaN"`, `value="inf"` or `value="-inf"`
+    String,
+    /// Fails the whole record, which is then not sent
+    Fail,
+}
+
+/// Counts the non-finite float fields encountered, regardless of the `NonFiniteFloats` policy
+#[derive(Debug, Default)]
+pub struct NonFiniteFloatCounters {
+    nan: AtomicU64,
+    positive_infinity: AtomicU64,
+    negative_infinity: AtomicU64,
+}
+
+impl NonFiniteFloatCounters {
+    /// Number of `NaN` fields
+    pub fn nan(&self) -> u64 {
+        self.nan.load(Ordering::Relaxed)
+    }
+
+    /// Number of positive infinite fields
+    pub fn positive_infinity(&self) -> u64 {
+        self.positive_infinity.load(Ordering::Relaxed)
+    }
+
+    /// Number of negative infinite fields
+    pub fn negative_infinity(&self) -> u64 {
+        self.negative_infinity.load(Ordering::Relaxed)
+    }
+
+    fn count(&self, float: f64) {
+        let counter = if float.is_nan() {
+            &self.nan
+        } else if float.is_sign_positive() {
+            &self.positive_infinity
+        } else {
+            &self.negative_infinity
+        };
+
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// How `None` and `()` values are written, see `ValueEncoding`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MissingValue {
+    /// Omits the tag or field
+    Omit,
+    /// Writes an empty string field. Tags are omitted, since tag values can not be empty
+    Empty,
+    /// Writes the boolean
+    Bool(bool),
+    /// Writes the integer
+    Int(i64),
+    /// Writes the float
+    Float(f64),
+    /// Writes the string
+    Str(&'static str),
+}
+
+/// How booleans are written, see `ValueEncoding`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Booleans {
+    /// `true` and `false` in tags, `t` and `f` in fields and for `None`, as written by earlier
+    /// versions
+    Legacy,
+    /// `true` and `false`
+    Long,
+    /// `t` and `f`
+    Short,
+}
+
+/// How booleans, `None` and `()` values are written
+///
+/// The default keeps the output of earlier versions, which differs between tags and fields, e.g.
+/// `()` is written as float field `0`. `ValueEncoding::UNIFORM` represents a value the same way
+/// regardless of where it ends up, but changes the types of existing fields.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ValueEncoding {
+    /// Defaults to `Booleans::Legacy`
+    pub booleans: Booleans,
+    /// Defaults to `MissingValue::Bool(false)`
+    pub none: MissingValue,
+    /// Defaults to `MissingValue::Float(0.0)`
+    pub unit: MissingValue,
+}
+
+impl ValueEncoding {
+    const DEFAULT: ValueEncoding = ValueEncoding {
+        booleans: Booleans::Legacy,
+        none: MissingValue::Bool(false),
+        unit: MissingValue::Float(0.0),
+    };
+
+    /// Writes booleans as `true` and `false`, `None` as `false` and `()` as integer `0`, in tags
+    /// and fields alike
+    pub const UNIFORM: ValueEncoding = ValueEncoding {
+        booleans: Booleans::Long,
+        none: MissingValue::Bool(false),
+        unit: MissingValue::Int(0),
+    };
+
+    fn tag_bool(&self, val: bool) -> &'static str {
+        match self.booleans {
+            Booleans::Short => short_bool(val),
+            Booleans::Legacy | Booleans::Long => long_bool(val)
+        }
+    }
+
+    /// Also used for missing values written as boolean tag
+    fn field_bool(&self, val: bool) -> &'static str {
+        match self.booleans {
+            Booleans::Legacy | Booleans::Short => short_bool(val),
+            Booleans::Long => long_bool(val)
+        }
+    }
+}
+
+impl Default for ValueEncoding {
+    fn default() -> Self {
+        ValueEncoding::DEFAULT
+    }
+}
+
+fn long_bool(val: bool) -> &'static str {
+    if val { "true" } else { "false" }
+}
+
+fn short_bool(val: bool) -> &'static str {
+    if val { "t" } else { "f" }
+}
+
+/// Writes `None` or `()` as the given `value` through the serializer
+fn emit_missing<S: slog::Serializer>(serializer: &mut S, key: Key, value: MissingValue, encoding: ValueEncoding,
+                                     is_tag: bool) -> slog::Result
+{
+    match value {
+        MissingValue::Omit => Ok(()),
+        MissingValue::Empty if is_tag => Ok(()),
+        MissingValue::Empty => serializer.emit_str(key, ""),
+        MissingValue::Bool(val) if is_tag => serializer.emit_str(key, encoding.field_bool(val)),
+        MissingValue::Bool(val) => serializer.emit_bool(key, val),
+        MissingValue::Int(val) => serializer.emit_i64(key, val),
+        MissingValue::Float(val) => serializer.emit_f64(key, val),
+        MissingValue::Str(val) => serializer.emit_str(key, val)
+    }
+}
+
+/// Maximum lengths in bytes, as written, i.e. after escaping
+///
+/// Tag values and string fields that are too long are truncated at a character boundary. Values
+/// that do not fit into the line at all are dropped. In both cases, the marker field is added.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LengthLimits {
+    pub tag_value: Option<usize>,
+    /// The length of string field values, without the quotes
+    pub string_field: Option<usize>,
+    /// The length of whole lines, including the timestamp and the line break
+    pub line: Option<usize>,
+    /// The key of the boolean field written if a value was truncated or dropped, defaults to
+    /// `_truncated`, i.e. `_truncated=t`
+    pub marker: Option<&'static str>,
+}
+
+impl LengthLimits {
+    const DEFAULT: LengthLimits = LengthLimits {
+        tag_value: None,
+        string_field: None,
+        line: None,
+        marker: Some("_truncated"),
+    };
+}
+
+impl Default for LengthLimits {
+    fn default() -> Self {
+        LengthLimits::DEFAULT
+    }
+}
+
+/// Which fields are written in addition to the message of errors, e.g. values logged as
+/// `"err" => #err`
+///
+/// The fields are named after the key of the error with the given suffix and are always written
+/// as fields, even if the message is written as tag.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ErrorValues {
+    /// The suffix of the string field with the messages of the `source()` chain, written if the
+    /// error has a source. Defaults to `_sources`, e.g. `err_sources="timed out: connection reset"`
+    pub sources: Option<&'static str>,
+    /// Separates the messages of the sources, defaults to `: `
+    pub separator: &'static str,
+    /// The suffix of the integer field with the number of errors in the chain, including the
+    /// error itself, e.g. `err_depth=3i`
+    pub depth: Option<&'static str>,
+}
+
+impl ErrorValues {
+    const DEFAULT: ErrorValues = ErrorValues {
+        sources: Some("_sources"),
+        separator: ": ",
+        depth: None,
+    };
+}
+
+impl Default for ErrorValues {
+    fn default() -> Self {
+        ErrorValues::DEFAULT
+    }
+}
+
+/// Displays the messages of the sources of an error
+struct Sources<'e>(&'e (dyn StdError + 'static), &'static str);
+
+impl<'e> fmt::Display for Sources<'e> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut source = self.0.source();
+        while let Some(error) = source {
+            write!(f, "{}", error)?;
+            source = error.source();
+            if source.is_some() {
+                f.write_str(self.1)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Precision of the timestamps appended to every line
+///
+/// Must match the `precision` setting of Telegraf or InfluxDB.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Precision {
+    Nanoseconds,
+    Microseconds,
+    Milliseconds,
+    Seconds,
+}
+
+impl Precision {
+    /// Converts the time since the unix epoch into a timestamp of this precision
+    pub(crate) fn timestamp(self, time: SystemTime) -> i128 {
+        let (duration, sign) = match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => (duration, 1),
+            Err(err) => (err.duration(), -1)
+        };
+
+        let timestamp = match self {
+            Precision::Nanoseconds => duration.as_nanos(),
+            Precision::Microseconds => duration.as_micros(),
+            Precision::Milliseconds => duration.as_millis(),
+            Precision::Seconds => duration.as_secs() as u128
+        };
+
+        sign * timestamp as i128
+    }
+}
+
+#[doc(hidden)]
+// Configures how values are written by TelegrafSocketSerializer. Like the serializer, it is not
+// considered stable API.
+#[derive(Clone, Debug, Default)]
+pub struct SerializerConfig {
+    pub unsigned_integers: UnsignedIntegers,
+    /// Timestamps are only written if a precision is set
+    pub timestamp_precision: Option<Precision>,
+    pub non_finite_floats: NonFiniteFloats,
+    pub non_finite_float_counters: Option<Arc<NonFiniteFloatCounters>>,
+    /// Sorts the tags by key and removes duplicate keys when the tags are finished
+    pub sort_tags: bool,
+    pub value_encoding: ValueEncoding,
+    pub key_mapping: KeyMapping,
+    pub length_limits: LengthLimits,
+    pub error_values: ErrorValues,
+    #[cfg(feature = "nested-values")]
+    pub nested_values: NestedValues,
+}
+
+impl SerializerConfig {
+    /// The bytes of a line that are not written by the tag and field serializers
+    fn line_overhead(&self) -> usize {
+        // The space before the fields and the line break
+        let mut overhead = 2;
+        if self.timestamp_precision.is_some() {
+            // A space, the sign and up to 20 digits
+            overhead += 22;
+        }
+        if let Some(marker) = self.length_limits.marker {
+            overhead += marker.len() + self.value_encoding.field_bool(true).len() + 2;
+        }
+        overhead
+    }
+
+    /// Returns the number of bytes that still fit into the line, if limited
+    fn line_remaining(&self, written: usize) -> Option<usize> {
+        self.length_limits.line.map(|line| line.saturating_sub(written + self.line_overhead()))
+    }
+}
+
+static DEFAULT_CONFIG: SerializerConfig = SerializerConfig {
+    unsigned_integers: UnsignedIntegers::Saturate,
+    timestamp_precision: None,
+    non_finite_floats: NonFiniteFloats::Drop,
+    non_finite_float_counters: None,
+    sort_tags: false,
+    value_encoding: ValueEncoding::DEFAULT,
+    key_mapping: KeyMapping::DEFAULT,
+    length_limits: LengthLimits::DEFAULT,
+    error_values: ErrorValues::DEFAULT,
+    #[cfg(feature = "nested-values")]
+    nested_values: NestedValues::DEFAULT,
+};
 
 #[doc(hidden)]
 // TelegrafSocketSerializer is only exported to use it in benchmarks. It is not considered
 // stable API.
 // Reference: https://docs.influxdata.com/influxdb/v1.8/write_protocols/line_protocol_tutorial/
-pub struct TelegrafSocketSerializer {
+pub struct TelegrafSocketSerializer<'c> {
+    /// The escaped measurement
+    measurement: String,
+    /// What set the measurement
+    measurement_origin: MeasurementOrigin,
+    /// The tags
     data: String,
+    /// The fields, kept apart from the tags since values can be routed to either of them
+    fields: String,
+    config: &'c SerializerConfig,
+    timestamp: Option<SystemTime>,
+    /// The start of each tag and the end of its key, only recorded if the tags are sorted
+    tags: Vec<(usize, usize)>,
+    /// Prepended to the keys of the record values
+    namespace: Option<String>,
+    /// Buffer for mapped keys
+    name: String,
+    /// The name of the tag checked by the cardinality guard
+    kept_name: KeptName,
+    /// Whether a value was truncated or dropped because of the length limits
+    truncated: bool,
 }
 
-impl TelegrafSocketSerializer {
+impl TelegrafSocketSerializer<'static> {
     pub fn start(measurement: &str, len: Option<usize>) -> Result<Self, slog::Error> {
-        let mut data = String::with_capacity(len.unwrap_or(120));
-        data.write_str(measurement)?;
+        TelegrafSocketSerializer::start_with_config(measurement, len, &DEFAULT_CONFIG)
+    }
+}
+
+impl<'c> TelegrafSocketSerializer<'c> {
+    pub fn start_with_config(measurement: &str, len: Option<usize>, config: &'c SerializerConfig)
+        -> Result<Self, slog::Error>
+    {
+        let mut escaped = String::with_capacity(measurement.len());
+        write_escaped(&mut escaped, Element::Measurement, format_args!("{}", measurement))?;
+
+        Ok(TelegrafSocketSerializer {
+            measurement: escaped,
+            measurement_origin: MeasurementOrigin::Start,
+            data: String::with_capacity(len.unwrap_or(120)),
+            fields: String::new(),
+            config,
+            timestamp: None,
+            tags: Vec::new(),
+            namespace: None,
+            name: String::new(),
+            kept_name: KeptName::default(),
+            truncated: false
+        })
+    }
+
+    pub fn tag_serializer(&mut self) -> TelegrafSocketTagSerializer<'_> {
+        TelegrafSocketTagSerializer { line: self.line_writer(Source::Builder, None, None, None) }
+    }
 
-        Ok(TelegrafSocketSerializer { data })
+    /// Creates a tag serializer for values from `source`, which resolves key conflicts and
+    /// writes the values the router places into the fields as fields
+    pub(crate) fn tag_serializer_for<'a>(&'a mut self, source: Source, keys: Option<&'a mut KeyResolver>,
+                                         router: Option<&'a Router>, guard: Option<&'a CardinalityGuard>)
+        -> TelegrafSocketTagSerializer<'a>
+    {
+        TelegrafSocketTagSerializer { line: self.line_writer(source, keys, router, guard) }
     }
 
-    pub fn tag_serializer(&mut self) -> TelegrafSocketTagSerializer {
-        TelegrafSocketTagSerializer { data: &mut self.data }
+    pub fn field_serializer(&mut self) -> TelegrafSocketFieldSerializer<'_> {
+        let skip_comma = !self.has_fields();
+        TelegrafSocketFieldSerializer { line: self.line_writer(Source::Record, None, None, None), skip_comma }
     }
 
-    pub fn field_serializer(&mut self) -> TelegrafSocketFieldSerializer {
-        TelegrafSocketFieldSerializer { data: &mut self.data, skip_comma: true }
+    /// Creates a field serializer for values from `source`, which resolves key conflicts and
+    /// writes the values the router places into the tags as tags
+    pub(crate) fn field_serializer_for<'a>(&'a mut self, source: Source, keys: Option<&'a mut KeyResolver>,
+                                           router: Option<&'a Router>, guard: Option<&'a CardinalityGuard>)
+        -> TelegrafSocketFieldSerializer<'a>
+    {
+        let skip_comma = !self.has_fields();
+        TelegrafSocketFieldSerializer { line: self.line_writer(source, keys, router, guard), skip_comma }
     }
 
+    /// Kept for compatibility, `end` separates the tags from the fields
     pub fn tag_value_break(&mut self) -> slog::Result {
-        self.data.write_char(' ').map_err(|e| e.into())
+        Ok(())
+    }
+
+    fn line_writer<'a>(&'a mut self, source: Source, keys: Option<&'a mut KeyResolver>,
+                       router: Option<&'a Router>, guard: Option<&'a CardinalityGuard>) -> LineWriter<'a>
+    {
+        let tags = if self.config.sort_tags { Some(&mut self.tags) } else { None };
+        let names = KeyNames {
+            resolver: keys,
+            mapping: &self.config.key_mapping,
+            namespace: self.namespace.as_deref(),
+            buffer: &mut self.name,
+            kept: &mut self.kept_name
+        };
+        let out = LineBuffers {
+            measurement: &mut self.measurement,
+            measurement_origin: &mut self.measurement_origin,
+            timestamp: &mut self.timestamp,
+            data: &mut self.data,
+            fields: &mut self.fields,
+            tags,
+            config: self.config,
+            truncated: &mut self.truncated
+        };
+        LineWriter { out, source, names, router, guard, placed: false }
+    }
+
+    /// Whether at least one field has been written
+    pub fn has_fields(&self) -> bool {
+        !self.fields.is_empty()
     }
 
-    pub fn end(self, insert_dummy_field: bool) -> Result<String, slog::Error> {
-        let mut data = self.data;
+    /// Sorts the tags written so far by key and keeps only the first tag of each key
+    fn sort_tags(&mut self) {
+        let region_start = match self.tags.first() {
+            Some(&(start, _)) => start,
+            None => return
+        };
+        let region = self.data.split_off(region_start);
+
+        let ends = self.tags.iter().skip(1).map(|&(start, _)| start - region_start).chain(Some(region.len()));
+        let mut tags: Vec<(&str, &str)> = self.tags.iter().zip(ends)
+            .map(|(&(start, key_end), end)| {
+                let (start, key_end) = (start - region_start, key_end - region_start);
+                // Skip the leading comma of the key
+                (&region[start + 1..key_end], &region[start..end])
+            })
+            .collect();
+
+        // The sort is stable, so the first of all tags with the same key is kept
+        tags.sort_by_key(|&(key, _)| key);
+        tags.dedup_by_key(|&mut (key, _)| key);
+
+        for (_, tag) in tags {
+            self.data.push_str(tag);
+        }
+        self.tags.clear();
+    }
+
+    /// Sets the namespace prepended to the keys of the record values, see `KeyMapping`
+    pub(crate) fn set_namespace(&mut self, namespace: Option<String>) {
+        self.namespace = namespace;
+    }
+
+    /// Sets the time of the line, which is written if the config specifies a timestamp precision
+    pub fn set_timestamp(&mut self, time: SystemTime) {
+        self.timestamp = Some(time);
+    }
+
+    pub fn end(mut self, insert_dummy_field: bool) -> Result<String, slog::Error> {
+        if self.config.sort_tags {
+            self.sort_tags();
+        }
+
+        let mut fields = self.fields;
         if insert_dummy_field {
             // The log statement contains no field, so insert a dummy field
-            data.write_fmt(format_args!("_dummy=1i"))?;
+            if !fields.is_empty() {
+                fields.write_char(',')?;
+            }
+            fields.write_fmt(format_args!("_dummy=1i"))?;
+        }
+        if let (true, Some(marker)) = (self.truncated, self.config.length_limits.marker) {
+            if !fields.is_empty() {
+                fields.write_char(',')?;
+            }
+            write_escaped(&mut fields, Element::Key, format_args!("{}", marker))?;
+            fields.write_fmt(format_args!("={}", self.config.value_encoding.field_bool(true)))?;
+        }
+
+        let mut data = self.measurement;
+        data.push_str(&self.data);
+        data.write_char(' ')?;
+        data.push_str(&fields);
+        if let (Some(time), Some(precision)) = (self.timestamp, self.config.timestamp_precision) {
+            data.write_fmt(format_args!(" {}", precision.timestamp(time)))?;
         }
         data.write_char('\n')?;
         Ok(data)
     }
 }
 
-pub struct TelegrafSocketTagSerializer<'a> {
+/// The elements of a line, each of which comes with its own set of characters that must be escaped
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Element {
+    /// Commas and spaces are escaped
+    Measurement,
+    /// Commas, equal signs and spaces are escaped. Used for tag keys, tag values and field keys
+    Key,
+    /// Double quotes and backslashes are escaped. Used for string field values
+    FieldString,
+}
+
+/// `fmt::Write` adapter that escapes everything written through it according to the line protocol
+struct Escaper<W> {
+    out: W,
+    element: Element,
+    /// The number of backslashes the input written so far ends with
+    trailing_backslashes: usize,
+    /// The number of bytes that may still be written, if limited
+    remaining: Option<usize>,
+    /// Whether input was dropped because of the limit
+    truncated: bool,
+}
+
+impl<W: Write> Escaper<W> {
+    fn new(out: W, element: Element, limit: Option<usize>) -> Self {
+        Escaper { out, element, trailing_backslashes: 0, remaining: limit, truncated: false }
+    }
+
+    /// Returns the escape sequence of the character, if it must be escaped
+    fn escape(&self, c: char) -> Option<&'static str> {
+        let escaped = match (c, self.element) {
+            ('"', Element::FieldString) => "\\\"",
+            ('\\', Element::FieldString) => "\\\\",
+            (_, Element::FieldString) => return None,
+            (',', _) => "\\,",
+            (' ', _) => "\\ ",
+            ('=', Element::Key) => "\\=",
+            // Line breaks and similar characters would terminate or corrupt the line
+            ('\n', _) => "\\n",
+            ('\r', _) => "\\r",
+            ('\t', _) => "\\t",
+            ('\x0c', _) => "\\f",
+            _ => return None
+        };
+        Some(escaped)
+    }
+
+    /// Writes the escape sequence, preceded by doubling the backslashes in front of it, so that
+    /// they do not escape each other or the sequence, e.g. `a\,b` is written as `a\\\,b`
+    fn write_escaped(&mut self, escaped: &str) -> fmt::Result {
+        for _ in 0..self.trailing_backslashes {
+            self.out.write_char('\\')?;
+        }
+        self.trailing_backslashes = 0;
+        self.out.write_str(escaped)
+    }
+
+    /// Counts the backslashes in front of the next character. String field values have their
+    /// backslashes escaped already
+    fn track_backslashes(&mut self, c: char) {
+        if c == '\\' && self.element != Element::FieldString {
+            self.trailing_backslashes += 1;
+        } else {
+            self.trailing_backslashes = 0;
+        }
+    }
+
+    /// Writes whole characters and escape sequences as long as they fit into `remaining` bytes
+    fn write_limited(&mut self, s: &str, mut remaining: usize) -> fmt::Result {
+        for c in s.chars() {
+            if self.truncated {
+                break;
+            }
+
+            let escaped = self.escape(c);
+            // Raw backslashes take room for doubling them as well
+            let raw_backslash = c == '\\' && self.element != Element::FieldString;
+            let len = if raw_backslash { 2 } else { escaped.map_or(c.len_utf8(), str::len) };
+            if len > remaining {
+                self.truncated = true;
+                break;
+            }
+
+            match escaped {
+                Some(escaped) => self.write_escaped(escaped)?,
+                None => {
+                    self.out.write_char(c)?;
+                    self.track_backslashes(c);
+                }
+            }
+            remaining -= len;
+        }
+
+        self.remaining = Some(remaining);
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Escaper<W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        if let Some(remaining) = self.remaining {
+            return self.write_limited(s, remaining);
+        }
+
+        let mut last = 0;
+        for (i, c) in s.char_indices() {
+            match self.escape(c) {
+                Some(escaped) => {
+                    self.out.write_str(&s[last..i])?;
+                    self.write_escaped(escaped)?;
+                    last = i + c.len_utf8();
+                },
+                None => self.track_backslashes(c)
+            }
+        }
+        self.out.write_str(&s[last..])
+    }
+}
+
+/// Writes `args` escaped for the given `element`
+fn write_escaped(data: &mut String, element: Element, args: fmt::Arguments) -> fmt::Result {
+    write_escaped_limited(data, element, args, None).map(|_| ())
+}
+
+/// Writes `args` escaped for the given `element`, but at most `limit` bytes of it. Returns
+/// whether the value was truncated
+fn write_escaped_limited(data: &mut String, element: Element, args: fmt::Arguments, limit: Option<usize>)
+    -> Result<bool, fmt::Error>
+{
+    let mut escaper = Escaper::new(&mut *data, element, limit);
+    escaper.write_fmt(args)?;
+    let truncated = escaper.truncated;
+
+    // Trailing backslashes would escape the following separator
+    escaper.write_escaped("")?;
+
+    Ok(truncated)
+}
+
+/// A name resolved ahead of writing the value, see `KeyNames::resolve_kept`
+#[derive(Default)]
+struct KeptName {
+    name: String,
+    /// Whether the next `KeyNames::resolve` returns the name
+    kept: bool,
+}
+
+/// Turns keys into the names written
+struct KeyNames<'a> {
+    resolver: Option<&'a mut KeyResolver>,
+    mapping: &'a KeyMapping,
+    namespace: Option<&'a str>,
+    /// Buffer for mapped keys
+    buffer: &'a mut String,
+    kept: &'a mut KeptName,
+}
+
+impl<'a> KeyNames<'a> {
+    /// Maps the key and resolves key conflicts, returns `None` if the value is dropped
+    fn resolve<'k>(&'k mut self, key: &'k Key, source: Source) -> Result<Option<&'k str>, slog::Error> {
+        if self.kept.kept {
+            self.kept.kept = false;
+            return Ok(Some(&self.kept.name));
+        }
+
+        let namespace = if source == Source::Record { self.namespace } else { None };
+        let name = self.mapping.map(key, namespace, self.buffer);
+        match &mut self.resolver {
+            Some(resolver) => resolver.resolve(name, source),
+            None => Ok(Some(name))
+        }
+    }
+
+    /// Resolves the name like `resolve` and keeps it, so that the next `resolve` returns it
+    /// without resolving the key again
+    fn resolve_kept(&mut self, key: &Key, source: Source) -> Result<Option<&str>, slog::Error> {
+        let namespace = if source == Source::Record { self.namespace } else { None };
+        let name = self.mapping.map(key, namespace, self.buffer);
+        let name = match &mut self.resolver {
+            Some(resolver) => resolver.resolve(name, source)?,
+            None => Some(name)
+        };
+
+        self.kept.name.clear();
+        self.kept.kept = name.is_some();
+        match name {
+            Some(name) => {
+                self.kept.name.push_str(name);
+                Ok(Some(&self.kept.name))
+            },
+            None => Ok(None)
+        }
+    }
+
+    /// Drops the kept name, if it was not used
+    fn release(&mut self) {
+        self.kept.kept = false;
+    }
+
+    fn reborrow(&mut self) -> KeyNames<'_> {
+        KeyNames {
+            resolver: self.resolver.as_deref_mut(),
+            mapping: self.mapping,
+            namespace: self.namespace,
+            buffer: &mut *self.buffer,
+            kept: &mut *self.kept
+        }
+    }
+}
+
+/// What set the measurement of a line, in increasing precedence
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum MeasurementOrigin {
+    /// The measurement the line was started with
+    Start,
+    /// A logger value with the measurement key
+    LoggerKey,
+    /// A record value with the measurement key
+    RecordKey,
+    /// A `Measurement` value
+    Value,
+}
+
+/// Finds the first non-empty value with the given key
+pub(crate) struct ValueFinder {
+    pub key: Key,
+    pub value: Option<String>,
+}
+
+impl slog::Serializer for ValueFinder {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        if self.value.is_none() && key == self.key {
+            let value = val.to_string();
+            if !value.is_empty() {
+                self.value = Some(value);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The buffers of a line, written to with already resolved names
+struct LineBuffers<'a> {
+    measurement: &'a mut String,
+    measurement_origin: &'a mut MeasurementOrigin,
+    timestamp: &'a mut Option<SystemTime>,
     data: &'a mut String,
+    fields: &'a mut String,
+    tags: Option<&'a mut Vec<(usize, usize)>>,
+    config: &'a SerializerConfig,
+    truncated: &'a mut bool,
+}
+
+impl<'a> LineBuffers<'a> {
+    fn reborrow(&mut self) -> LineBuffers<'_> {
+        LineBuffers {
+            measurement: &mut *self.measurement,
+            measurement_origin: &mut *self.measurement_origin,
+            timestamp: &mut *self.timestamp,
+            data: &mut *self.data,
+            fields: &mut *self.fields,
+            tags: self.tags.as_deref_mut(),
+            config: self.config,
+            truncated: &mut *self.truncated
+        }
+    }
+
+    /// Replaces the measurement, truncated to the room left in the line. Keeps the measurement
+    /// if nothing of the new one fits
+    fn set_measurement(&mut self, origin: MeasurementOrigin, val: fmt::Arguments) -> slog::Result {
+        let limit = self.config.line_remaining(self.data.len() + self.fields.len());
+        let mut measurement = String::new();
+        if write_escaped_limited(&mut measurement, Element::Measurement, val, limit)? {
+            *self.truncated = true;
+        }
+        if !measurement.is_empty() {
+            *self.measurement = measurement;
+            *self.measurement_origin = origin;
+        }
+        Ok(())
+    }
+
+    /// Writes a tag, truncates its value to the limits and drops it if the value is empty or
+    /// nothing of it fits
+    fn write_tag(&mut self, name: &str, val: fmt::Arguments) -> slog::Result {
+        let LineBuffers { measurement, data, fields, tags, config, truncated, .. } = self;
+
+        let start = data.len();
+        data.push(',');
+        write_escaped(data, Element::Key, format_args!("{}", name))?;
+        let key_end = data.len();
+        data.push('=');
+
+        let line_remaining = config.line_remaining(measurement.len() + data.len() + fields.len());
+        let limit = config.length_limits.tag_value.into_iter().chain(line_remaining).min();
+        if write_escaped_limited(data, Element::Key, val, limit)? {
+            **truncated = true;
+        }
+        if data.len() == key_end + 1 {
+            // Tag values must not be empty
+            data.truncate(start);
+            return Ok(());
+        }
+
+        if let Some(tags) = tags {
+            tags.push((start, key_end));
+        }
+
+        Ok(())
+    }
+
+    /// Writes the separating comma, if required, the escaped key and the already formatted value
+    fn write_field(&mut self, name: &str, val: fmt::Arguments) -> slog::Result {
+        self.write_field_value(name, val, false)
+    }
+
+    /// Writes a quoted and escaped string value
+    fn write_string(&mut self, name: &str, val: fmt::Arguments) -> slog::Result {
+        self.write_field_value(name, val, true)
+    }
+
+    /// Writes a field, drops it if it does not fit into the line and truncates string values
+    fn write_field_value(&mut self, name: &str, val: fmt::Arguments, string: bool) -> slog::Result {
+        let LineBuffers { measurement, data, fields, config, truncated, .. } = self;
+
+        let start = fields.len();
+        if !fields.is_empty() {
+            fields.write_char(',')?;
+        }
+        write_escaped(fields, Element::Key, format_args!("{}", name))?;
+        fields.write_char('=')?;
+
+        let line_remaining = config.line_remaining(measurement.len() + data.len() + fields.len());
+        let fits = if string {
+            // Leave room for the quotes
+            match line_remaining.map(|remaining| remaining.checked_sub(2)) {
+                Some(None) => false,
+                line_remaining => {
+                    let limit = config.length_limits.string_field.into_iter().chain(line_remaining.flatten()).min();
+                    fields.write_char('"')?;
+                    if write_escaped_limited(fields, Element::FieldString, val, limit)? {
+                        **truncated = true;
+                    }
+                    fields.write_char('"')?;
+                    true
+                }
+            }
+        } else {
+            let value_start = fields.len();
+            fields.write_fmt(val)?;
+            line_remaining.map_or(true, |remaining| fields.len() - value_start <= remaining)
+        };
+
+        if !fits {
+            fields.truncate(start);
+            **truncated = true;
+        }
+        Ok(())
+    }
+
+    fn write_bool(&mut self, name: &str, val: bool) -> slog::Result {
+        self.write_field(name, format_args!("{}", self.config.value_encoding.field_bool(val)))
+    }
+
+    fn write_int(&mut self, name: &str, integer: i64) -> slog::Result {
+        self.write_field(name, format_args!("{}i", integer))
+    }
+
+    /// Writes unsigned integers that always fit into an `i64`
+    fn write_small_unsigned(&mut self, name: &str, integer: u32) -> slog::Result {
+        match self.config.unsigned_integers {
+            UnsignedIntegers::Native => self.write_unsigned(name, integer as u64),
+            _ => self.write_int(name, integer as i64)
+        }
+    }
+
+    fn write_unsigned(&mut self, name: &str, integer: u64) -> slog::Result {
+        match self.config.unsigned_integers {
+            UnsignedIntegers::Native => self.write_field(name, format_args!("{}u", integer)),
+            UnsignedIntegers::Saturate => self.write_int(name, i64::try_from(integer).unwrap_or(i64::MAX)),
+            UnsignedIntegers::Float => self.write_float(name, integer as f64),
+            UnsignedIntegers::String => self.write_string(name, format_args!("{}", integer))
+        }
+    }
+
+    fn write_float(&mut self, name: &str, float: f64) -> slog::Result {
+        if float.is_finite() {
+            return self.write_field(name, format_args!("{}", float));
+        }
+
+        if let Some(counters) = &self.config.non_finite_float_counters {
+            counters.count(float);
+        }
+
+        match self.config.non_finite_floats {
+            NonFiniteFloats::Drop => Ok(()),
+            NonFiniteFloats::Replace(replacement) => self.write_field(name, format_args!("{}", replacement)),
+            NonFiniteFloats::String => self.write_string(name, format_args!("{}", float)),
+            NonFiniteFloats::Fail => Err(LineError::NonFiniteFloat(name.to_string()).into())
+        }
+    }
+
+    /// Writes the fields selected by `ErrorValues` for the error written as `name`
+    fn write_error_fields(&mut self, name: &str, error: &(dyn StdError + 'static)) -> slog::Result {
+        let error_values = self.config.error_values;
+        let suffixed = |suffix| format!("{}{}", name, suffix);
+
+        if let (Some(suffix), Some(_)) = (error_values.sources, error.source()) {
+            let sources = Sources(error, error_values.separator);
+            self.write_string(&suffixed(suffix), format_args!("{}", sources))?;
+        }
+        if let Some(suffix) = error_values.depth {
+            let depth = std::iter::successors(Some(error), |&error| error.source()).count();
+            self.write_int(&suffixed(suffix), depth as i64)?;
+        }
+        Ok(())
+    }
+
+    /// Writes a leaf of a nested value as tag. Tags of nested values are not limited by the
+    /// cardinality guard
+    #[cfg(feature = "nested-values")]
+    fn write_tag_leaf(&mut self, name: &str, leaf: Leaf) -> slog::Result {
+        let encoding = self.config.value_encoding;
+        let missing = match leaf {
+            Leaf::Bool(val) => return self.write_tag(name, format_args!("{}", encoding.tag_bool(val))),
+            Leaf::Int(val) => return self.write_tag(name, format_args!("{}", val)),
+            Leaf::Unsigned(val) => return self.write_tag(name, format_args!("{}", val)),
+            Leaf::Float(val) => return self.write_tag(name, format_args!("{}", val)),
+            Leaf::Str(val) => return self.write_tag(name, format_args!("{}", val)),
+            Leaf::None => encoding.none,
+            Leaf::Unit => encoding.unit
+        };
+
+        match missing {
+            MissingValue::Omit | MissingValue::Empty => Ok(()),
+            MissingValue::Bool(val) => self.write_tag(name, format_args!("{}", encoding.field_bool(val))),
+            MissingValue::Int(val) => self.write_tag(name, format_args!("{}", val)),
+            MissingValue::Float(val) => self.write_tag(name, format_args!("{}", val)),
+            MissingValue::Str(val) => self.write_tag(name, format_args!("{}", val))
+        }
+    }
+
+    /// Writes a leaf of a nested value as field
+    #[cfg(feature = "nested-values")]
+    fn write_field_leaf(&mut self, name: &str, leaf: Leaf) -> slog::Result {
+        let missing = match leaf {
+            Leaf::Bool(val) => return self.write_bool(name, val),
+            Leaf::Int(val) => return self.write_int(name, val),
+            Leaf::Unsigned(val) => return self.write_unsigned(name, val),
+            Leaf::Float(val) => return self.write_float(name, val),
+            Leaf::Str(val) => return self.write_string(name, format_args!("{}", val)),
+            Leaf::None => self.config.value_encoding.none,
+            Leaf::Unit => self.config.value_encoding.unit
+        };
+
+        match missing {
+            MissingValue::Omit => Ok(()),
+            MissingValue::Empty => self.write_string(name, format_args!("")),
+            MissingValue::Bool(val) => self.write_bool(name, val),
+            MissingValue::Int(val) => self.write_int(name, val),
+            MissingValue::Float(val) => self.write_float(name, val),
+            MissingValue::Str(val) => self.write_string(name, format_args!("{}", val))
+        }
+    }
+}
+
+/// The parts of a line written by the tag and field serializers
+struct LineWriter<'a> {
+    out: LineBuffers<'a>,
+    /// The source of the values written
+    source: Source,
+    names: KeyNames<'a>,
+    router: Option<&'a Router>,
+    /// Kept for values already routed, unlike the router
+    guard: Option<&'a CardinalityGuard>,
+    /// Whether the value was routed already, in which case the placement of `Tag` and `Field`
+    /// values is ignored
+    placed: bool,
+}
+
+impl<'a> LineWriter<'a> {
+    /// Returns where the value is placed, `None` if it is not written, or `default` if there is
+    /// no router. `Tag` and `Field` values override the router
+    fn placement(&self, key: &Key, default: Placement) -> Option<Placement> {
+        let placement = match self.router {
+            Some(router) => router.route(key, self.source)?,
+            None => default
+        };
+
+        let directed = if self.placed { None } else { Directives::current().placement };
+        Some(directed.unwrap_or(placement))
+    }
+
+    /// Sets the time or the measurement of the line for `Timestamp` and `Measurement` values.
+    /// Returns whether the value was one of them
+    fn write_line_value(&mut self, val: &fmt::Arguments) -> Result<bool, slog::Error> {
+        let directives = Directives::current();
+        if let Some(time) = directives.timestamp {
+            *self.out.timestamp = Some(time);
+            return Ok(true);
+        }
+        if directives.measurement {
+            self.out.set_measurement(MeasurementOrigin::Value, *val)?;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    /// Takes the measurement from a value the router does not write, if it has the measurement
+    /// key. The newest logger value is replaced by the newest record value, and both by
+    /// `Measurement` values
+    fn capture<F>(&mut self, key: &Key, serialize: F) -> slog::Result
+        where F: FnOnce(&mut ValueFinder) -> slog::Result
+    {
+        let origin = match self.source {
+            Source::Builder => return Ok(()),
+            Source::Logger => MeasurementOrigin::LoggerKey,
+            Source::Record => MeasurementOrigin::RecordKey,
+        };
+        let measurement_key = self.router.and_then(|router| router.measurement_key);
+        if measurement_key != Some(key) || *self.out.measurement_origin >= origin {
+            return Ok(());
+        }
+
+        let mut finder = ValueFinder { key, value: None };
+        serialize(&mut finder)?;
+        match finder.value {
+            Some(measurement) => self.out.set_measurement(origin, format_args!("{}", measurement)),
+            None => Ok(())
+        }
+    }
+
+    /// Records the tag value in the cardinality guard, if any, and returns what is written for it,
+    /// or `None` if the value is dropped. The guard tracks the resolved name, which is kept for
+    /// writing the value, see `KeyNames::resolve_kept`
+    fn check_cardinality(&mut self, key: Key, val: &fmt::Arguments) -> Result<Option<Checked>, slog::Error> {
+        let guard = match self.guard {
+            Some(guard) => guard,
+            None => return Ok(Some(Checked::Tag))
+        };
+        Ok(self.names.resolve_kept(&key, self.source)?.map(|name| guard.check(name, val)))
+    }
+
+    /// Resolves the name of the key and calls `write` with it, unless the value is dropped
+    fn with_name<F>(&mut self, key: Key, write: F) -> slog::Result
+        where F: FnOnce(&mut LineBuffers<'a>, &str) -> slog::Result
+    {
+        match self.names.resolve(&key, self.source)? {
+            Some(name) => write(&mut self.out, name),
+            None => Ok(())
+        }
+    }
+
+    /// Flattens the nested value into leaves named after the key and calls `write` for each
+    #[cfg(feature = "nested-values")]
+    fn write_nested<F>(&mut self, key: Key, val: &dyn slog::SerdeValue, mut write: F) -> slog::Result
+        where F: FnMut(&mut LineBuffers<'a>, &str, Leaf) -> slog::Result
+    {
+        self.with_name(key, |out, name| {
+            let nested_values = &out.config.nested_values;
+            let dropped = nested::flatten(val, name, nested_values, |name, leaf| write(out, name, leaf))?;
+            if dropped {
+                *out.truncated = true;
+            }
+            Ok(())
+        })
+    }
+
+    /// Reborrows the writer without router, for values already routed
+    fn routed(&mut self) -> LineWriter<'_> {
+        LineWriter {
+            out: self.out.reborrow(),
+            source: self.source,
+            names: self.names.reborrow(),
+            router: None,
+            guard: self.guard,
+            placed: true
+        }
+    }
+
+    fn tag_serializer(&mut self) -> TelegrafSocketTagSerializer<'_> {
+        TelegrafSocketTagSerializer { line: self.routed() }
+    }
+
+    fn field_serializer(&mut self) -> TelegrafSocketFieldSerializer<'_> {
+        let line = self.routed();
+        let skip_comma = line.out.fields.is_empty();
+        TelegrafSocketFieldSerializer { line, skip_comma }
+    }
+}
+
+/// Returns early if the value is not written or if the router places it elsewhere, in which case
+/// it is forwarded to the serializer returned by `$other`. Values that are not written may still
+/// set the measurement
+macro_rules! route {
+    ($self:ident, $placement:ident, $other:ident, $f:ident, $key:ident $(, $val:ident)?) => {
+        match $self.line.placement(&$key, Placement::$placement) {
+            None => return $self.line.capture(&$key, |finder| slog::Serializer::$f(finder, $key $(, $val)?)),
+            Some(Placement::$placement) => {},
+            Some(_) => return $self.line.$other().$f($key $(, $val)?)
+        }
+    };
+}
+
+pub struct TelegrafSocketTagSerializer<'a> {
+    line: LineWriter<'a>,
+}
+
+impl<'a> TelegrafSocketTagSerializer<'a> {
+    fn write_tag(&mut self, key: Key, val: fmt::Arguments) -> slog::Result {
+        self.line.with_name(key, |out, name| out.write_tag(name, val))
+    }
+
+    /// Writes the tag as the cardinality guard decides, as field with `write_field`
+    fn write_checked<F>(&mut self, key: Key, val: fmt::Arguments, write_field: F) -> slog::Result
+        where F: FnOnce(&mut TelegrafSocketFieldSerializer<'_>) -> slog::Result
+    {
+        let written = match self.line.check_cardinality(key, &val)? {
+            None => Ok(()),
+            Some(Checked::Tag) => self.write_tag(key, val),
+            Some(Checked::Replace(other)) => self.write_tag(key, format_args!("{}", other)),
+            Some(Checked::Field) => write_field(&mut self.line.field_serializer())
+        };
+        self.line.names.release();
+        written
+    }
 }
 
 macro_rules! emit_m {
     ($f:ident, $arg:ty) => {
         fn $f(&mut self, key: Key, val: $arg) -> slog::Result {
-            self.data.write_fmt(format_args!(",{}={}", key, val)).map_err(|e| e.into())
+            route!(self, Tag, field_serializer, $f, key, val);
+            self.write_checked(key, format_args!("{}", val), |fields| fields.$f(key, val))
         }
     };
 }
@@ -66,138 +1188,200 @@ impl<'a> slog::Serializer for TelegrafSocketTagSerializer<'a> {
     emit_m!(emit_i64, i64);
     emit_m!(emit_f32, f32);
     emit_m!(emit_f64, f64);
-    emit_m!(emit_bool, bool);
     emit_m!(emit_char, char);
     emit_m!(emit_str, &str);
 
-    // Serialize '()' as '0'
+    fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
+        route!(self, Tag, field_serializer, emit_bool, key, val);
+        let val_str = self.line.out.config.value_encoding.tag_bool(val);
+        self.write_checked(key, format_args!("{}", val_str), |fields| fields.emit_bool(key, val))
+    }
+
     fn emit_unit(&mut self, key: Key) -> slog::Result {
-        self.data.write_fmt(format_args!(",{}=0", key)).map_err(|e| e.into())
+        route!(self, Tag, field_serializer, emit_unit, key);
+        let encoding = self.line.out.config.value_encoding;
+        emit_missing(self, key, encoding.unit, encoding, true)
     }
 
-    // Serialize 'None' as 'false'
     fn emit_none(&mut self, key: Key) -> slog::Result {
-        self.data.write_fmt(format_args!(",{}=f", key)).map_err(|e| e.into())
+        route!(self, Tag, field_serializer, emit_none, key);
+        let encoding = self.line.out.config.value_encoding;
+        emit_missing(self, key, encoding.none, encoding, true)
+    }
+
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        if self.line.write_line_value(val)? {
+            return Ok(());
+        }
+        route!(self, Tag, field_serializer, emit_arguments, key, val);
+        self.write_checked(key, *val, |fields| fields.emit_arguments(key, val))
     }
 
-    emit_m!(emit_arguments, &fmt::Arguments);
+    fn emit_error(&mut self, key: Key, error: &(dyn StdError + 'static)) -> slog::Result {
+        route!(self, Tag, field_serializer, emit_error, key, error);
+        let written = match self.line.check_cardinality(key, &format_args!("{}", error))? {
+            None => Ok(()),
+            Some(Checked::Field) => self.line.field_serializer().emit_error(key, error),
+            Some(checked) => self.line.with_name(key, |out, name| {
+                match checked {
+                    Checked::Replace(other) => out.write_tag(name, format_args!("{}", other))?,
+                    _ => out.write_tag(name, format_args!("{}", error))?
+                }
+                out.write_error_fields(name, error)
+            })
+        };
+        self.line.names.release();
+        written
+    }
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: Key, val: &dyn slog::SerdeValue) -> slog::Result {
+        route!(self, Tag, field_serializer, emit_serde, key, val);
+        self.line.write_nested(key, val, |out, name, leaf| out.write_tag_leaf(name, leaf))
+    }
 }
 
 pub struct TelegrafSocketFieldSerializer<'a> {
-    data: &'a mut String,
-    pub skip_comma: bool
+    line: LineWriter<'a>,
+    /// Whether no field has been written yet, i.e. whether `end` needs the dummy field
+    pub skip_comma: bool,
 }
 
 impl<'a> TelegrafSocketFieldSerializer<'a> {
-    fn maybe_write_comma(&mut self) -> slog::Result {
-        if self.skip_comma {
-            self.skip_comma = false;
-        } else {
-            self.data.write_char(',')?;
-        }
-
-        Ok(())
+    fn with_name<F>(&mut self, key: Key, write: F) -> slog::Result
+        where F: FnOnce(&mut LineBuffers<'a>, &str) -> slog::Result
+    {
+        let result = self.line.with_name(key, write);
+        self.skip_comma = self.line.out.fields.is_empty();
+        result
     }
 
-    fn write_int(&mut self, key: Key, integer: i64) -> slog::Result {
-        self.maybe_write_comma()?;
-        self.data.write_fmt(format_args!("{}={}i", key, integer)).map_err(|e| e.into())
-    }
-
-    fn write_float(&mut self, key: Key, float: f64) -> slog::Result {
-        self.maybe_write_comma()?;
-        self.data.write_fmt(format_args!("{}={}", key, float)).map_err(|e| e.into())
+    #[cfg(feature = "nested-values")]
+    fn write_nested<F>(&mut self, key: Key, val: &dyn slog::SerdeValue, write: F) -> slog::Result
+        where F: FnMut(&mut LineBuffers<'a>, &str, Leaf) -> slog::Result
+    {
+        let result = self.line.write_nested(key, val, write);
+        self.skip_comma = self.line.out.fields.is_empty();
+        result
     }
 }
 
 impl<'a> slog::Serializer for TelegrafSocketFieldSerializer<'a> {
     fn emit_u8(&mut self, key: Key, val: u8) -> slog::Result {
-        self.write_int(key, val as i64)
+        route!(self, Field, tag_serializer, emit_u8, key, val);
+        self.with_name(key, |out, name| out.write_small_unsigned(name, val as u32))
     }
 
     fn emit_i8(&mut self, key: Key, val: i8) -> slog::Result {
-        self.write_int(key, val as i64)
+        route!(self, Field, tag_serializer, emit_i8, key, val);
+        self.with_name(key, |out, name| out.write_int(name, val as i64))
     }
 
     fn emit_u16(&mut self, key: Key, val: u16) -> slog::Result {
-        self.write_int(key, val as i64)
+        route!(self, Field, tag_serializer, emit_u16, key, val);
+        self.with_name(key, |out, name| out.write_small_unsigned(name, val as u32))
     }
 
     fn emit_i16(&mut self, key: Key, val: i16) -> slog::Result {
-        self.write_int(key, val as i64)
+        route!(self, Field, tag_serializer, emit_i16, key, val);
+        self.with_name(key, |out, name| out.write_int(name, val as i64))
     }
 
     fn emit_usize(&mut self, key: Key, val: usize) -> slog::Result {
-        self.write_int(key, val as i64)
+        route!(self, Field, tag_serializer, emit_usize, key, val);
+        self.with_name(key, |out, name| out.write_unsigned(name, val as u64))
     }
 
     fn emit_isize(&mut self, key: Key, val: isize) -> slog::Result {
-        self.write_int(key, val as i64)
+        route!(self, Field, tag_serializer, emit_isize, key, val);
+        self.with_name(key, |out, name| out.write_int(name, val as i64))
     }
 
     fn emit_u32(&mut self, key: Key, val: u32) -> slog::Result {
-        self.write_int(key, val as i64)
+        route!(self, Field, tag_serializer, emit_u32, key, val);
+        self.with_name(key, |out, name| out.write_small_unsigned(name, val))
     }
 
     fn emit_i32(&mut self, key: Key, val: i32) -> slog::Result {
-        self.write_int(key, val as i64)
+        route!(self, Field, tag_serializer, emit_i32, key, val);
+        self.with_name(key, |out, name| out.write_int(name, val as i64))
     }
 
     fn emit_u64(&mut self, key: Key, val: u64) -> slog::Result {
-        self.write_int(key, val as i64)
+        route!(self, Field, tag_serializer, emit_u64, key, val);
+        if Directives::current().unsigned {
+            return self.with_name(key, |out, name| out.write_field(name, format_args!("{}u", val)));
+        }
+        self.with_name(key, |out, name| out.write_unsigned(name, val))
     }
 
     fn emit_i64(&mut self, key: Key, val: i64) -> slog::Result {
-        self.write_int(key, val)
+        route!(self, Field, tag_serializer, emit_i64, key, val);
+        self.with_name(key, |out, name| out.write_int(name, val))
     }
 
 
     fn emit_f32(&mut self, key: Key, val: f32) -> slog::Result {
-        self.write_float(key, val as f64)
+        route!(self, Field, tag_serializer, emit_f32, key, val);
+        self.with_name(key, |out, name| out.write_float(name, val as f64))
     }
 
     fn emit_f64(&mut self, key: Key, val: f64) -> slog::Result {
-        self.write_float(key, val)
+        route!(self, Field, tag_serializer, emit_f64, key, val);
+        self.with_name(key, |out, name| out.write_float(name, val))
     }
 
 
     fn emit_bool(&mut self, key: Key, val: bool) -> slog::Result {
-        self.maybe_write_comma()?;
-        if val {
-            self.data.write_fmt(format_args!("{}=t", key)).map_err(|e| e.into())
-        } else {
-            self.data.write_fmt(format_args!("{}=f", key)).map_err(|e| e.into())
-        }
+        route!(self, Field, tag_serializer, emit_bool, key, val);
+        self.with_name(key, |out, name| out.write_bool(name, val))
     }
 
 
     fn emit_char(&mut self, key: Key, val: char) -> slog::Result {
-        self.maybe_write_comma()?;
-        self.data.write_fmt(format_args!(r#"{}="{}""#, key, val)).map_err(|e| e.into())
+        route!(self, Field, tag_serializer, emit_char, key, val);
+        self.with_name(key, |out, name| out.write_string(name, format_args!("{}", val)))
     }
 
     fn emit_str(&mut self, key: Key, val: &str) -> slog::Result {
-        self.maybe_write_comma()?;
-        self.data.write_fmt(format_args!(r#"{}="{}""#, key, val)).map_err(|e| e.into())
+        route!(self, Field, tag_serializer, emit_str, key, val);
+        self.with_name(key, |out, name| out.write_string(name, format_args!("{}", val)))
     }
 
 
-    // Serialize '()' as '0'
     fn emit_unit(&mut self, key: Key) -> slog::Result {
-        self.maybe_write_comma()?;
-        self.data.write_fmt(format_args!("{}=0", key)).map_err(|e| e.into())
+        route!(self, Field, tag_serializer, emit_unit, key);
+        let encoding = self.line.out.config.value_encoding;
+        emit_missing(self, key, encoding.unit, encoding, false)
     }
 
-    // Serialize 'None' as 'false'
     fn emit_none(&mut self, key: Key) -> slog::Result {
-        self.maybe_write_comma()?;
-        self.data.write_fmt(format_args!("{}=f", key)).map_err(|e| e.into())
+        route!(self, Field, tag_serializer, emit_none, key);
+        let encoding = self.line.out.config.value_encoding;
+        emit_missing(self, key, encoding.none, encoding, false)
     }
 
 
     fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
-        self.maybe_write_comma()?;
-        self.data.write_fmt(format_args!("{}=\"{}\"", key, val)).map_err(|e| e.into())
+        if self.line.write_line_value(val)? {
+            return Ok(());
+        }
+        route!(self, Field, tag_serializer, emit_arguments, key, val);
+        self.with_name(key, |out, name| out.write_string(name, *val))
+    }
+
+    fn emit_error(&mut self, key: Key, error: &(dyn StdError + 'static)) -> slog::Result {
+        route!(self, Field, tag_serializer, emit_error, key, error);
+        self.with_name(key, |out, name| {
+            out.write_string(name, format_args!("{}", error))?;
+            out.write_error_fields(name, error)
+        })
+    }
+
+    #[cfg(feature = "nested-values")]
+    fn emit_serde(&mut self, key: Key, val: &dyn slog::SerdeValue) -> slog::Result {
+        route!(self, Field, tag_serializer, emit_serde, key, val);
+        self.write_nested(key, val, |out, name, leaf| out.write_field_leaf(name, leaf))
     }
 }
 
@@ -205,7 +1389,7 @@ impl<'a> slog::Serializer for TelegrafSocketFieldSerializer<'a> {
 #[cfg(test)]
 mod test {
     use super::*;
-    use slog::{KV, Record, o};
+    use slog::{KV, Record, Serializer, o};
 
     fn do_serializer<S: slog::Serializer>(serializer: &mut S) {
         // rinfo_static and the values passed to Record::new are irrelevant for this test and
@@ -223,18 +1407,18 @@ mod test {
         };
 
         o!(
-            "int0" => 10 as u8,
-            "int1" => -10 as i8,
-            "int2" => 10000 as u16,
-            "int3" => -10000 as i16,
-            "int4" => 2_000_000_000 as u32,
-            "int5" => -2_000_000_000 as i32,
-            "int6" => 2_000_000_000 as usize,
-            "int7" => -2_000_000_000 as isize,
-            "int8" => 2_000_000_000_000 as u64,
-            "int9" => -2_000_000_000_000 as i64,
-            "float0" => 13.2 as f32,
-            "float1" => -105.2 as f64,
+            "int0" => 10_u8,
+            "int1" => -10_i8,
+            "int2" => 10000_u16,
+            "int3" => -10000_i16,
+            "int4" => 2_000_000_000_u32,
+            "int5" => -2_000_000_000_i32,
+            "int6" => 2_000_000_000_usize,
+            "int7" => -2_000_000_000_isize,
+            "int8" => 2_000_000_000_000_u64,
+            "int9" => -2_000_000_000_000_i64,
+            "float0" => 13.2_f32,
+            "float1" => -105.2_f64,
             "string0" => "foo",
             "string1" => "1.2.1",
             "char0" => 'x',
@@ -258,7 +1442,7 @@ mod test {
 
         let field_serializer = serializer.field_serializer();
         let insert_dummy_field = field_serializer.skip_comma;
-        assert_eq!(insert_dummy_field, true);
+        assert!(insert_dummy_field);
 
         let data = serializer.end(insert_dummy_field).unwrap();
         assert_eq!(data, "test_measurement,none=f,unit=0,bool1=false,bool0=true,char0=x,string1=1.2.1,string0=foo,float1=-105.2,float0=13.2,int9=-2000000000000,int8=2000000000000,int7=-2000000000,int6=2000000000,int5=-2000000000,int4=2000000000,int3=-10000,int2=10000,int1=-10,int0=10 _dummy=1i\n");
@@ -273,10 +1457,317 @@ mod test {
         do_serializer(&mut field_serializer);
 
         let insert_dummy_field = field_serializer.skip_comma;
-        assert_eq!(insert_dummy_field, false);
+        assert!(!insert_dummy_field);
 
         let data = serializer.end(insert_dummy_field).unwrap();
 
         assert_eq!(data, "test_measurement none=f,unit=0,bool1=f,bool0=t,char0=\"x\",string1=\"1.2.1\",string0=\"foo\",float1=-105.2,float0=13.199999809265137,int9=-2000000000000i,int8=2000000000000i,int7=-2000000000i,int6=2000000000i,int5=-2000000000i,int4=2000000000i,int3=-10000i,int2=10000i,int1=-10i,int0=10i\n");
     }
-}
\ No newline at end of file
+
+    /// Serializes the values of `do_serializer` and the wrapper values
+    fn do_wrapped_serializer<S: slog::Serializer>(serializer: &mut S) {
+        use crate::values::{Tag, Field, Unsigned, Timestamp, Measurement};
+
+        do_serializer(serializer);
+        let rinfo_static = record_static!(slog::Level::Info, "slog_tag");
+        o!(
+            "tag0" => Tag(10_u8),
+            "tag1" => Tag("foo"),
+            "field0" => Field(-10_i8),
+            "field1" => Field("foo"),
+            "unsigned0" => Unsigned(u64::MAX),
+            "unsigned1" => Tag(Unsigned(10)),
+            "time" => Timestamp(UNIX_EPOCH + std::time::Duration::from_secs(1)),
+            "measurement" => Measurement("other measurement"),
+        ).serialize(&Record::new(&rinfo_static, &format_args!("msg"), slog::BorrowedKV(&())), serializer).unwrap();
+    }
+
+    #[test]
+    fn test_wrapped_values() {
+        let config = SerializerConfig { timestamp_precision: Some(Precision::Seconds), ..Default::default() };
+        let mut serializer = TelegrafSocketSerializer::start_with_config("test_measurement", None, &config).unwrap();
+        do_wrapped_serializer(&mut serializer.tag_serializer());
+        let data = serializer.end(false).unwrap();
+        assert!(data.starts_with("other\\ measurement,none=f,"), "{}", data);
+        assert!(data.ends_with(",int0=10,unsigned1=10,unsigned0=18446744073709551615,tag1=foo,tag0=10 field1=\"foo\",field0=-10i 1\n"), "{}", data);
+
+        let mut serializer = TelegrafSocketSerializer::start_with_config("test_measurement", None, &config).unwrap();
+        do_wrapped_serializer(&mut serializer.field_serializer());
+        let data = serializer.end(false).unwrap();
+        assert!(data.starts_with("other\\ measurement,unsigned1=10,tag1=foo,tag0=10 none=f,"), "{}", data);
+        assert!(data.ends_with(",int0=10i,unsigned0=18446744073709551615u,field1=\"foo\",field0=-10i 1\n"), "{}", data);
+
+        // The wrappers do not affect values serialized after them
+        let mut serializer = TelegrafSocketSerializer::start("m", None).unwrap();
+        do_serializer(&mut serializer.field_serializer());
+        assert!(serializer.end(false).unwrap().starts_with("m none=f,"));
+    }
+
+    /// Serializes `tags` and `fields` into a complete line
+    fn serialize_line<T: KV, F: KV>(measurement: &str, tags: &T, fields: &F) -> String {
+        let rinfo_static = record_static!(slog::Level::Info, "slog_tag");
+        let record = |kv: &dyn KV, serializer: &mut dyn slog::Serializer| {
+            kv.serialize(&Record::new(&rinfo_static, &format_args!("msg"), slog::BorrowedKV(&())),
+                         serializer).unwrap()
+        };
+
+        let mut serializer = TelegrafSocketSerializer::start(measurement, None).unwrap();
+        record(tags, &mut serializer.tag_serializer());
+        record(fields, &mut serializer.field_serializer());
+
+        let insert_dummy_field = !serializer.has_fields();
+        serializer.end(insert_dummy_field).unwrap()
+    }
+
+    #[test]
+    fn test_escaping() {
+        let data = serialize_line("my measurement,=\"", &o!(
+            "tag key,=" => "tag value,=\"",
+            "line" => "a\nb\rc\td\x0ce",
+            "backslash" => "a\\",
+        ), &o!(
+            "field key,=" => 1,
+        ));
+        assert_eq!(data, "my\\ measurement\\,=\",backslash=a\\\\,line=a\\nb\\rc\\td\\fe,tag\\ key\\,\\==tag\\ value\\,\\=\" field\\ key\\,\\==1i\n");
+
+        let mut serializer = TelegrafSocketSerializer::start("m", None).unwrap();
+        serializer.tag_serializer().emit_arguments("args", &format_args!("{} {}", "a,", "b=")).unwrap();
+        assert_eq!(serializer.end(true).unwrap(), "m,args=a\\,\\ b\\= _dummy=1i\n");
+
+        // Tags with empty values are omitted, since tag values can not be empty
+        let mut serializer = TelegrafSocketSerializer::start("m", None).unwrap();
+        let mut tag_serializer = serializer.tag_serializer();
+        tag_serializer.emit_str("a", "x").unwrap();
+        tag_serializer.emit_str("empty", "").unwrap();
+        tag_serializer.emit_arguments("msg", &format_args!("{}", "")).unwrap();
+        serializer.field_serializer().emit_str("f", "").unwrap();
+        let data = serializer.end(false).unwrap();
+        assert_eq!(data, "m,a=x f=\"\"\n");
+        crate::validate::validate(&data, None).unwrap();
+
+        // Backslashes in front of escaped characters are doubled, so that they do not escape the
+        // escape sequence
+        let data = serialize_line("m\\,x", &o!(
+            "comma" => "a\\,b",
+            "space" => "a\\ b",
+            "equal" => "a\\=b",
+            "run" => "a\\\\,b",
+            "plain" => "a\\b",
+            "trailing" => "a\\\\",
+        ), &o!(
+            "k\\,ey" => 1,
+        ));
+        assert_eq!(data, "m\\\\\\,x,trailing=a\\\\\\\\,plain=a\\b,run=a\\\\\\\\\\,b,equal=a\\\\\\=b,space=a\\\\\\ b,\
+                          comma=a\\\\\\,b k\\\\\\,ey=1i\n");
+        crate::validate::validate(&data, None).unwrap();
+
+        let mut serializer = TelegrafSocketSerializer::start("m", None).unwrap();
+        serializer.tag_serializer().emit_arguments("args", &format_args!("{}{}", "a\\", ",b\\")).unwrap();
+        let data = serializer.end(true).unwrap();
+        assert_eq!(data, "m,args=a\\\\\\,b\\\\ _dummy=1i\n");
+        crate::validate::validate(&data, None).unwrap();
+    }
+
+    #[test]
+    fn test_string_field_escaping() {
+        let data = serialize_line("m", &o!(), &o!(
+            "str" => r#"{"error": "C:\path"}"#,
+            "char" => '"',
+            "backslash" => "a\\",
+            "spaces" => "a b,c=d",
+        ));
+        assert_eq!(data, r#"m spaces="a b,c=d",backslash="a\\",char="\"",str="{\"error\": \"C:\\path\"}""#.to_string() + "\n");
+
+        let mut serializer = TelegrafSocketSerializer::start("m", None).unwrap();
+        serializer.field_serializer().emit_arguments("args", &format_args!("{}\\{}", '"', "\"")).unwrap();
+        assert_eq!(serializer.end(false).unwrap(), "m args=\"\\\"\\\\\\\"\"\n");
+    }
+
+    #[test]
+    fn test_unsigned_integers() {
+        let serialize = |unsigned_integers| {
+            let config = SerializerConfig { unsigned_integers, ..Default::default() };
+            let mut serializer = TelegrafSocketSerializer::start_with_config("m", None, &config).unwrap();
+            let mut field_serializer = serializer.field_serializer();
+            field_serializer.emit_u8("small", 10).unwrap();
+            field_serializer.emit_usize("usize", 20).unwrap();
+            field_serializer.emit_u64("big", u64::MAX).unwrap();
+            serializer.end(false).unwrap()
+        };
+
+        assert_eq!(serialize(UnsignedIntegers::Native), "m small=10u,usize=20u,big=18446744073709551615u\n");
+        assert_eq!(serialize(UnsignedIntegers::Saturate), "m small=10i,usize=20i,big=9223372036854775807i\n");
+        assert_eq!(serialize(UnsignedIntegers::Float), "m small=10i,usize=20,big=18446744073709552000\n");
+        assert_eq!(serialize(UnsignedIntegers::String), "m small=10i,usize=\"20\",big=\"18446744073709551615\"\n");
+    }
+
+    #[test]
+    fn test_timestamp() {
+        let serialize = |timestamp_precision, time| {
+            let config = SerializerConfig { timestamp_precision, ..Default::default() };
+            let mut serializer = TelegrafSocketSerializer::start_with_config("m", None, &config).unwrap();
+            serializer.set_timestamp(time);
+            serializer.end(true).unwrap()
+        };
+
+        let time = UNIX_EPOCH + std::time::Duration::new(1_600_000_000, 123_456_789);
+        assert_eq!(serialize(None, time), "m _dummy=1i\n");
+        assert_eq!(serialize(Some(Precision::Nanoseconds), time), "m _dummy=1i 1600000000123456789\n");
+        assert_eq!(serialize(Some(Precision::Microseconds), time), "m _dummy=1i 1600000000123456\n");
+        assert_eq!(serialize(Some(Precision::Milliseconds), time), "m _dummy=1i 1600000000123\n");
+        assert_eq!(serialize(Some(Precision::Seconds), time), "m _dummy=1i 1600000000\n");
+
+        let time = UNIX_EPOCH - std::time::Duration::from_secs(10);
+        assert_eq!(serialize(Some(Precision::Seconds), time), "m _dummy=1i -10\n");
+    }
+
+    #[derive(Debug)]
+    struct TestError(&'static str, Option<Box<TestError>>);
+
+    impl fmt::Display for TestError {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str(self.0)
+        }
+    }
+
+    impl StdError for TestError {
+        fn source(&self) -> Option<&(dyn StdError + 'static)> {
+            self.1.as_deref().map(|source| source as _)
+        }
+    }
+
+    #[test]
+    fn test_error_values() {
+        let error = TestError("request failed", Some(Box::new(TestError("timed out", Some(Box::new(TestError("reset \"", None)))))));
+        let serialize = |error_values, tag| {
+            let config = SerializerConfig { error_values, ..Default::default() };
+            let mut serializer = TelegrafSocketSerializer::start_with_config("m", None, &config).unwrap();
+            if tag {
+                serializer.tag_serializer().emit_error("err", &error).unwrap();
+            } else {
+                serializer.field_serializer().emit_error("err", &error).unwrap();
+            }
+            serializer.end(false).unwrap()
+        };
+
+        assert_eq!(serialize(ErrorValues::default(), false),
+                   "m err=\"request failed\",err_sources=\"timed out: reset \\\"\"\n");
+        assert_eq!(serialize(ErrorValues::default(), true),
+                   "m,err=request\\ failed err_sources=\"timed out: reset \\\"\"\n");
+
+        let depth = ErrorValues { sources: None, depth: Some(".depth"), ..Default::default() };
+        assert_eq!(serialize(depth, false), "m err=\"request failed\",err.depth=3i\n");
+    }
+
+    #[test]
+    fn test_non_finite_floats() {
+        let counters = Arc::new(NonFiniteFloatCounters::default());
+        let serialize = |non_finite_floats| {
+            let config = SerializerConfig {
+                non_finite_floats,
+                non_finite_float_counters: Some(counters.clone()),
+                ..Default::default()
+            };
+            let mut serializer = TelegrafSocketSerializer::start_with_config("m", None, &config).unwrap();
+            let mut field_serializer = serializer.field_serializer();
+            field_serializer.emit_f64("nan", f64::NAN)?;
+            field_serializer.emit_f32("inf", f32::INFINITY)?;
+            field_serializer.emit_f64("ninf", f64::NEG_INFINITY)?;
+            field_serializer.emit_f64("finite", 1.5)?;
+            serializer.end(false)
+        };
+
+        assert_eq!(serialize(NonFiniteFloats::Drop).unwrap(), "m finite=1.5\n");
+        assert_eq!(serialize(NonFiniteFloats::Replace(0.0)).unwrap(), "m nan=0,inf=0,ninf=0,finite=1.5\n");
+        assert_eq!(serialize(NonFiniteFloats::String).unwrap(), "m nan=\"NaN\",inf=\"inf\",ninf=\"-inf\",finite=1.5\n");
+        assert!(serialize(NonFiniteFloats::Fail).is_err());
+
+        // The failing serializer stops at the first non-finite value
+        assert_eq!(counters.nan(), 4);
+        assert_eq!(counters.positive_infinity(), 3);
+        assert_eq!(counters.negative_infinity(), 3);
+    }
+
+    #[test]
+    fn test_sorted_tags() {
+        let config = SerializerConfig { sort_tags: true, ..Default::default() };
+        let mut serializer = TelegrafSocketSerializer::start_with_config("test_measurement", None, &config).unwrap();
+        do_serializer(&mut serializer.tag_serializer());
+
+        let data = serializer.end(true).unwrap();
+        assert_eq!(data, "test_measurement,bool0=true,bool1=false,char0=x,float0=13.2,float1=-105.2,int0=10,int1=-10,int2=10000,int3=-10000,int4=2000000000,int5=-2000000000,int6=2000000000,int7=-2000000000,int8=2000000000000,int9=-2000000000000,none=f,string0=foo,string1=1.2.1,unit=0 _dummy=1i\n");
+
+        let mut serializer = TelegrafSocketSerializer::start_with_config("m", None, &config).unwrap();
+        let mut tag_serializer = serializer.tag_serializer();
+        tag_serializer.emit_str("b", "first").unwrap();
+        tag_serializer.emit_str("a,b", "x").unwrap();
+        tag_serializer.emit_str("a", "y").unwrap();
+        tag_serializer.emit_str("b", "second").unwrap();
+        serializer.field_serializer().emit_i64("f", 1).unwrap();
+
+        assert_eq!(serializer.end(false).unwrap(), "m,a=y,a\\,b=x,b=first f=1i\n");
+    }
+
+    #[test]
+    fn test_value_encoding() {
+        let serialize = |value_encoding| {
+            let config = SerializerConfig { value_encoding, ..Default::default() };
+            let mut serializer = TelegrafSocketSerializer::start_with_config("m", None, &config).unwrap();
+            let mut tag_serializer = serializer.tag_serializer();
+            tag_serializer.emit_bool("bool", true).unwrap();
+            tag_serializer.emit_none("none").unwrap();
+            tag_serializer.emit_unit("unit").unwrap();
+
+            let mut field_serializer = serializer.field_serializer();
+            field_serializer.emit_bool("bool", true).unwrap();
+            field_serializer.emit_none("none").unwrap();
+            field_serializer.emit_unit("unit").unwrap();
+            serializer.end(false).unwrap()
+        };
+
+        assert_eq!(serialize(ValueEncoding::default()), "m,bool=true,none=f,unit=0 bool=t,none=f,unit=0\n");
+        assert_eq!(serialize(ValueEncoding::UNIFORM), "m,bool=true,none=false,unit=0 bool=true,none=false,unit=0i\n");
+        assert_eq!(serialize(ValueEncoding { booleans: Booleans::Short, none: MissingValue::Omit, unit: MissingValue::Empty }),
+                   "m,bool=t bool=t,unit=\"\"\n");
+        assert_eq!(serialize(ValueEncoding { booleans: Booleans::Long, none: MissingValue::Str("none"), unit: MissingValue::Bool(true) }),
+                   "m,bool=true,none=none,unit=true bool=true,none=\"none\",unit=true\n");
+    }
+
+    #[test]
+    fn test_length_limits() {
+        let serialize = |length_limits, tags: &[(&'static str, &str)], fields: &[(&'static str, &str)]| {
+            let config = SerializerConfig { length_limits, ..Default::default() };
+            let mut serializer = TelegrafSocketSerializer::start_with_config("m", None, &config).unwrap();
+            for &(key, val) in tags {
+                serializer.tag_serializer().emit_str(key, val).unwrap();
+            }
+            for &(key, val) in fields {
+                serializer.field_serializer().emit_str(key, val).unwrap();
+            }
+            serializer.field_serializer().emit_i64("int", 100).unwrap();
+            serializer.end(false).unwrap()
+        };
+
+        let limits = LengthLimits { tag_value: Some(4), string_field: Some(5), ..Default::default() };
+        assert_eq!(serialize(limits, &[("a", "abcd")], &[("s", "abcde")]), "m,a=abcd s=\"abcde\",int=100i\n");
+        assert_eq!(serialize(limits, &[("a", "abcdef")], &[("s", "abcdef")]), "m,a=abcd s=\"abcde\",int=100i,_truncated=t\n");
+
+        // Multi-byte characters and escape sequences are never split
+        assert_eq!(serialize(limits, &[("a", "abcäb"), ("b", "ab c")], &[("s", "ab\"cd")]), "m,a=abc,b=ab\\  s=\"ab\\\"c\",int=100i,_truncated=t\n");
+        assert_eq!(serialize(limits, &[("a", "abc\\d"), ("b", "ä")], &[]), "m,a=abc,b=ä int=100i,_truncated=t\n");
+        assert_eq!(serialize(limits, &[("a", "ab\\")], &[]), "m,a=ab\\\\ int=100i\n");
+
+        let limits = LengthLimits { tag_value: Some(0), marker: None, ..Default::default() };
+        assert_eq!(serialize(limits, &[("a", "abc")], &[]), "m int=100i\n");
+
+        // Fields that do not fit into the line are dropped, strings are truncated to fit. Room
+        // for the marker is always left
+        let limits = LengthLimits { line: Some(47), ..Default::default() };
+        assert_eq!(serialize(limits, &[("tag", "value")], &[("s", "str")]), "m,tag=value s=\"str\",int=100i\n");
+        let line = serialize(limits, &[("tag", "value")], &[("s", "long string value!")]);
+        assert_eq!(line, "m,tag=value s=\"long string value\",_truncated=t\n");
+        assert_eq!(line.len(), 47);
+        let limits = LengthLimits { line: Some(20), ..Default::default() };
+        assert_eq!(serialize(limits, &[("tag", "long value")], &[]), "m _truncated=t\n");
+    }
+}
@@ -1,6 +1,16 @@
 use std::{net, io};
+#[cfg(unix)]
+use std::os::unix::net::{UnixDatagram, UnixStream};
+#[cfg(unix)]
+use std::path::PathBuf;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 use url::Url;
-use crate::Error;
+use crate::{Error, Precision};
+use crate::http::{HttpConnection, WriteParameters, Batching};
 use std::io::Write;
 
 /// Telegraf client
@@ -21,24 +31,437 @@ impl Client {
         })
     }
 
+    /// Sets the delays between attempts to re-establish a broken TCP or Unix stream connection
+    /// and between attempts to post a failed HTTP batch, see `Backoff`
+    pub fn backoff(mut self, backoff: Backoff) -> Self {
+        match &mut self.connection {
+            Connection::Tcp(tcp) => tcp.backoff = backoff,
+            Connection::Http(http) => http.set_backoff(backoff),
+            #[cfg(unix)]
+            Connection::Unix(unix) => unix.backoff = backoff,
+            _ => {}
+        }
+        self
+    }
+
+    /// Sets how long writing a line to a TCP or Unix stream connection may take, defaults to
+    /// 5 seconds
+    ///
+    /// If a line could only be written partly, its rest is written before the next line. If that
+    /// fails as well, the connection is re-established. For HTTP, this limits how long posting a
+    /// batch and receiving the response may take.
+    pub fn write_timeout(mut self, timeout: Duration) -> Result<Self, Error> {
+        match &mut self.connection {
+            Connection::Tcp(tcp) => tcp.set_write_timeout(timeout)?,
+            Connection::Http(http) => http.set_timeout(timeout),
+            #[cfg(unix)]
+            Connection::Unix(unix) => unix.set_write_timeout(timeout)?,
+            _ => {}
+        }
+        Ok(self)
+    }
+
+    /// Sets the maximum size of the datagrams sent over a Unix datagram socket. Longer lines are
+    /// dropped
+    ///
+    /// Defaults to 2048 bytes on macOS and iOS, where this is the limit of the platform, and to
+    /// 64 KiB elsewhere.
+    pub fn max_datagram_size(mut self, max_size: usize) -> Self {
+        #[cfg(unix)]
+        if let Connection::Unixgram(unixgram) = &mut self.connection {
+            unixgram.max_size = max_size;
+        }
+        #[cfg(not(unix))]
+        let _ = max_size;
+        self
+    }
+
+    /// Counts the lines that were not sent over a Unix datagram socket, see `DroppedDatagrams`
+    pub fn dropped_datagrams(mut self, counters: Arc<DroppedDatagrams>) -> Self {
+        #[cfg(unix)]
+        if let Connection::Unixgram(unixgram) = &mut self.connection {
+            unixgram.dropped = Some(counters);
+        }
+        #[cfg(not(unix))]
+        let _ = counters;
+        self
+    }
+
+    /// Sets the `db`, `rp`, `precision` and `consistency` parameters of the writes to an HTTP
+    /// endpoint, see `WriteParameters`
+    pub fn write_parameters(mut self, parameters: WriteParameters) -> Self {
+        if let Connection::Http(http) = &mut self.connection {
+            http.set_parameters(&parameters);
+        }
+        self
+    }
+
+    /// Sets the `precision` parameter of the writes to an HTTP endpoint to the precision of the
+    /// timestamps written by the drain
+    pub(crate) fn set_timestamp_precision(&mut self, precision: Precision) {
+        if let Connection::Http(http) = &mut self.connection {
+            http.set_parameters(&WriteParameters { precision: Some(precision), ..WriteParameters::default() });
+        }
+    }
+
+    /// Sets how many lines are posted at once to an HTTP endpoint and how long they may wait for
+    /// further lines, see `Batching`
+    pub fn batching(mut self, batching: Batching) -> Self {
+        if let Connection::Http(http) = &mut self.connection {
+            http.set_batching(batching);
+        }
+        self
+    }
+
     pub fn write(&mut self, bytes:&[u8]) -> io::Result<()> {
-        self.connection.write(bytes).map(|_| ())
+        self.connection.write(bytes)
+    }
+
+    /// Posts the lines batched for an HTTP endpoint. The other connections write every line
+    /// immediately
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.connection {
+            Connection::Http(http) => http.flush(),
+            _ => Ok(())
+        }
     }
 }
 
+/// Counts the lines that were not sent over a Unix datagram socket. Such lines are reported as
+/// error by `Client::write` as well
+#[derive(Debug, Default)]
+pub struct DroppedDatagrams {
+    too_large: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl DroppedDatagrams {
+    /// Number of lines longer than `Client::max_datagram_size`
+    pub fn too_large(&self) -> u64 {
+        self.too_large.load(Ordering::Relaxed)
+    }
+
+    /// Number of lines that could not be sent, e.g. because the receive buffer of Telegraf was full
+    pub fn failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+/// Delays between attempts to re-establish a broken connection
+///
+/// A connection that is found broken is re-established before the next write. If that fails,
+/// writes fail without further attempts until the delay has passed. The delay after the n-th
+/// failed attempt in a row is `initial * multiplier^(n-1)`, at most `max`, shortened by a random
+/// share of up to `jitter` so that many clients do not reconnect at once. The addresses of the
+/// URL are resolved again on every attempt.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Backoff {
+    /// Defaults to 100 ms
+    pub initial: Duration,
+    /// Defaults to 30 s
+    pub max: Duration,
+    /// Defaults to 2
+    pub multiplier: f64,
+    /// Between 0 and 1, defaults to 0.2
+    pub jitter: f64,
+}
+
+impl Backoff {
+    /// Returns the delay after the given number of failed attempts in a row
+    pub(crate) fn delay(&self, failures: u32) -> Duration {
+        let exponent = failures.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = (self.initial.as_secs_f64() * self.multiplier.powi(exponent)).min(self.max.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0) * random_fraction();
+        Duration::try_from_secs_f64(delay * (1.0 - jitter)).unwrap_or(self.max)
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            initial: Duration::from_millis(100),
+            max: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+/// Returns a random number between 0 and 1
+fn random_fraction() -> f64 {
+    // The keys of every RandomState are random
+    let random = RandomState::new().build_hasher().finish();
+    (random >> 11) as f64 / (1u64 << 53) as f64
+}
+
 enum Connection {
-    Tcp(net::TcpStream),
-    Udp(net::UdpSocket)
+    Tcp(StreamConnection<net::TcpStream>),
+    #[cfg(unix)]
+    Unix(StreamConnection<UnixStream>),
+    #[cfg(unix)]
+    Unixgram(DatagramConnection),
+    Udp(net::UdpSocket),
+    Http(HttpConnection)
+}
+
+/// A Unix datagram socket connected to the socket of Telegraf
+#[cfg(unix)]
+struct DatagramConnection {
+    path: PathBuf,
+    socket: UnixDatagram,
+    max_size: usize,
+    dropped: Option<Arc<DroppedDatagrams>>,
+}
+
+#[cfg(unix)]
+impl DatagramConnection {
+    #[cfg(any(target_os = "macos", target_os = "ios"))]
+    const MAX_SIZE: usize = 2048;
+    #[cfg(not(any(target_os = "macos", target_os = "ios")))]
+    const MAX_SIZE: usize = 64 * 1024;
+
+    fn connect(path: PathBuf) -> Result<Self, Error> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(&path)?;
+        // Like UDP, lines are dropped instead of waiting for Telegraf
+        socket.set_nonblocking(true)?;
+
+        Ok(DatagramConnection { path, socket, max_size: DatagramConnection::MAX_SIZE, dropped: None })
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if bytes.len() > self.max_size {
+            self.count(|dropped| &dropped.too_large);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                format!("The line of {} bytes exceeds the maximum datagram size of {} bytes", bytes.len(), self.max_size)));
+        }
+
+        let sent = match self.socket.send(bytes) {
+            // Telegraf was restarted and bound a new socket to the path
+            Err(err) if matches!(err.kind(), io::ErrorKind::ConnectionRefused | io::ErrorKind::NotConnected
+                                             | io::ErrorKind::NotFound) => {
+                self.socket.connect(&self.path).and_then(|()| self.socket.send(bytes))
+            },
+            sent => sent
+        };
+
+        sent.map(|_| ()).map_err(|err| {
+            self.count(|dropped| &dropped.failed);
+            err
+        })
+    }
+
+    fn count<F: Fn(&DroppedDatagrams) -> &AtomicU64>(&self, counter: F) {
+        if let Some(dropped) = &self.dropped {
+            counter(dropped).fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Writers whose writes can be limited in time, see `write_all_before`
+trait TimedWrite: Write {
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+/// The stream sockets of a `StreamConnection`
+trait Stream: TimedWrite + Sized {
+    type Address;
+
+    fn open(address: &Self::Address) -> io::Result<Self>;
+}
+
+impl TimedWrite for net::TcpStream {
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        net::TcpStream::set_write_timeout(self, timeout)
+    }
+}
+
+impl Stream for net::TcpStream {
+    type Address = Url;
+
+    fn open(url: &Url) -> io::Result<Self> {
+        // Resolved on every attempt, since the addresses may change while Telegraf is restarted
+        let addr = url.socket_addrs(|| None)?;
+        net::TcpStream::connect(&*addr)
+    }
+}
+
+#[cfg(unix)]
+impl TimedWrite for UnixStream {
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for UnixStream {
+    type Address = PathBuf;
+
+    fn open(path: &PathBuf) -> io::Result<Self> {
+        UnixStream::connect(path)
+    }
+}
+
+/// A TCP or Unix stream connection that is re-established once it is broken
+struct StreamConnection<S: Stream> {
+    address: S::Address,
+    stream: Option<S>,
+    backoff: Backoff,
+    /// The number of failed connection attempts since the last connection
+    failures: u32,
+    /// No connection is attempted before this time
+    retry_at: Option<Instant>,
+    write_timeout: Duration,
+    /// The rest of a line that could only be written partly, written before the next line
+    pending: Vec<u8>,
+}
+
+impl<S: Stream> StreamConnection<S> {
+    fn connect(address: S::Address) -> Result<Self, Error> {
+        let write_timeout = Duration::from_secs(5);
+        let stream = StreamConnection::open(&address, write_timeout)?;
+        Ok(StreamConnection {
+            address,
+            stream: Some(stream),
+            backoff: Backoff::default(),
+            failures: 0,
+            retry_at: None,
+            write_timeout,
+            pending: Vec::new()
+        })
+    }
+
+    fn open(address: &S::Address, write_timeout: Duration) -> io::Result<S> {
+        let stream = S::open(address)?;
+        stream.set_write_timeout(Some(write_timeout))?;
+        Ok(stream)
+    }
+
+    fn set_write_timeout(&mut self, timeout: Duration) -> io::Result<()> {
+        if let Some(stream) = &self.stream {
+            stream.set_write_timeout(Some(timeout))?;
+        }
+        self.write_timeout = timeout;
+        Ok(())
+    }
+
+    fn reconnect(&mut self) -> io::Result<S> {
+        if let Some(retry_at) = self.retry_at {
+            if Instant::now() < retry_at {
+                return Err(io::Error::new(io::ErrorKind::NotConnected, "Waiting to reconnect to Telegraf"));
+            }
+        }
+
+        match StreamConnection::open(&self.address, self.write_timeout) {
+            Ok(stream) => {
+                // The new connection starts in sync
+                self.pending.clear();
+                self.failures = 0;
+                self.retry_at = None;
+                Ok(stream)
+            },
+            Err(err) => {
+                self.failures = self.failures.saturating_add(1);
+                self.retry_at = Some(Instant::now() + self.backoff.delay(self.failures));
+                Err(err)
+            }
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        let connected = self.stream.is_some();
+        match self.write_line(bytes) {
+            // The peer closed the connection, e.g. because Telegraf was restarted. This only shows
+            // when writing, so the line is written once more on a new connection
+            Err(err) if connected && is_disconnect(&err) => self.write_line(bytes),
+            written => written
+        }
+    }
+
+    fn write_line(&mut self, bytes: &[u8]) -> io::Result<()> {
+        // The stream is put back unless it is broken, in which case the next write reconnects
+        let mut stream = match self.stream.take() {
+            Some(stream) => stream,
+            None => self.reconnect()?
+        };
+
+        let deadline = Instant::now() + self.write_timeout;
+        if !self.pending.is_empty() {
+            // If the line can not be finished, the next write starts over with a new connection
+            write_all_before(&mut stream, &self.pending, deadline).map_err(|(_, err)| err)?;
+            self.pending.clear();
+        }
+
+        match write_all_before(&mut stream, bytes, deadline) {
+            Ok(()) => {
+                self.stream = Some(stream);
+                Ok(())
+            },
+            Err((written, err)) if written > 0 && err.kind() == io::ErrorKind::TimedOut => {
+                self.pending.extend_from_slice(&bytes[written..]);
+                self.stream = Some(stream);
+                Err(err)
+            },
+            Err((_, err)) if err.kind() == io::ErrorKind::TimedOut => {
+                // Nothing was written, so the stream is still in sync
+                self.stream = Some(stream);
+                Err(err)
+            },
+            Err((_, err)) => Err(err)
+        }
+    }
+}
+
+/// Writes all bytes, retrying partial and interrupted writes until the deadline has passed. Each
+/// write may only take the time left. On failure, returns the number of bytes written with the
+/// error
+fn write_all_before<W: TimedWrite>(out: &mut W, bytes: &[u8], deadline: Instant) -> Result<(), (usize, io::Error)> {
+    let mut written = 0;
+    while written < bytes.len() {
+        let now = Instant::now();
+        if now >= deadline {
+            return Err((written, io::Error::new(io::ErrorKind::TimedOut, "Writing to Telegraf timed out")));
+        }
+        out.set_write_timeout(Some(deadline - now)).map_err(|err| (written, err))?;
+
+        match out.write(&bytes[written..]) {
+            Ok(0) => return Err((written, io::ErrorKind::WriteZero.into())),
+            Ok(n) => written += n,
+            // The write timeout of the socket elapsed, which is noticed by the next iteration
+            Err(err) if matches!(err.kind(), io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+                                             | io::ErrorKind::TimedOut) => {},
+            Err(err) => return Err((written, err))
+        }
+    }
+    Ok(())
+}
+
+/// Whether the write failed because the peer closed the connection
+fn is_disconnect(err: &io::Error) -> bool {
+    matches!(err.kind(), io::ErrorKind::BrokenPipe | io::ErrorKind::ConnectionReset
+                         | io::ErrorKind::ConnectionAborted | io::ErrorKind::NotConnected)
 }
 
 impl Connection {
     pub fn new(url: String) -> Result<Self, Error> {
         let url = Url::parse(&url)?;
-        let addr = url.socket_addrs(|| None)?;
 
         match url.scheme() {
-            "tcp" => Ok(Connection::Tcp(net::TcpStream::connect(&*addr)?)),
+            "tcp" => Ok(Connection::Tcp(StreamConnection::connect(url)?)),
+            #[cfg(unix)]
+            "unix" => {
+                let path = url.to_file_path()
+                    .map_err(|_| Error::Custom(format!("'{}' is not a valid socket path", url)))?;
+                Ok(Connection::Unix(StreamConnection::connect(path)?))
+            },
+            #[cfg(unix)]
+            "unixgram" => {
+                let path = url.to_file_path()
+                    .map_err(|_| Error::Custom(format!("'{}' is not a valid socket path", url)))?;
+                Ok(Connection::Unixgram(DatagramConnection::connect(path)?))
+            },
             "udp" => {
+                let addr = url.socket_addrs(|| None)?;
                 // This will let the OS choose the ip+port
                 let socket = net::UdpSocket::bind(&[net::SocketAddr::from(([0, 0, 0, 0], 0))][..])?;
                 socket.connect(&*addr)?;
@@ -46,15 +469,21 @@ impl Connection {
 
                 Ok(Connection::Udp(socket))
             },
-            "" => Err(Error::Custom("Please specify the protocol 'tcp' or 'udp'".to_string())),
-            _ => Err(Error::Custom("Only 'tcp' and 'udp' is currently supported".to_string()))
+            "http" => Ok(Connection::Http(HttpConnection::new(url)?)),
+            "" => Err(Error::Custom("Please specify the protocol 'tcp', 'udp', 'unix', 'unixgram' or 'http'".to_string())),
+            _ => Err(Error::Custom("Only 'tcp', 'udp', 'unix', 'unixgram' and 'http' are currently supported".to_string()))
         }
     }
 
-    pub fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+    pub fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
         match self {
-            Connection::Tcp(tcp_stream) => tcp_stream.write(bytes),
-            Connection::Udp(udp_socket) => udp_socket.send(bytes)
+            Connection::Tcp(tcp) => tcp.write(bytes),
+            #[cfg(unix)]
+            Connection::Unix(unix) => unix.write(bytes),
+            #[cfg(unix)]
+            Connection::Unixgram(unixgram) => unixgram.write(bytes),
+            Connection::Udp(udp_socket) => udp_socket.send(bytes).map(|_| ()),
+            Connection::Http(http) => http.write(bytes)
         }
     }
 }
@@ -68,6 +497,257 @@ mod test {
         assert!(Connection::new("udp://127.0.0.1:12345".into()).is_ok());
 
         assert!(Connection::new("127.0.0.1:12345".into()).is_err());
-        assert!(Connection::new("http://127.0.0.1:12345".into()).is_err());
+        assert!(Connection::new("https://127.0.0.1:12345".into()).is_err());
+        assert!(Connection::new("http://127.0.0.1:12345".into()).is_ok());
+    }
+
+    #[test]
+    fn test_backoff() {
+        let backoff = Backoff { jitter: 0.0, ..Backoff::default() };
+        assert_eq!(backoff.delay(1), Duration::from_millis(100));
+        assert_eq!(backoff.delay(4), Duration::from_millis(800));
+        assert_eq!(backoff.delay(100), Duration::from_secs(30));
+
+        let backoff = Backoff { jitter: 0.5, ..Backoff::default() };
+        for _ in 0..100 {
+            let delay = backoff.delay(2);
+            assert!(delay > Duration::from_millis(100) && delay <= Duration::from_millis(200), "{:?}", delay);
+        }
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_tcp_reconnect() {
+        use std::io::{BufRead, BufReader};
+        use std::thread;
+
+        let read_line = |stream: net::TcpStream| {
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            line
+        };
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let backoff = Backoff { initial: Duration::from_millis(300), jitter: 0.0, ..Backoff::default() };
+        let mut client = Client::new(format!("tcp://{}", addr)).unwrap().backoff(backoff);
+
+        let (stream, _) = listener.accept().unwrap();
+        client.write(b"first\n").unwrap();
+        assert_eq!(read_line(stream), "first\n");
+
+        // The peer closed the connection. The first write that fails reconnects and writes the
+        // line again
+        thread::sleep(Duration::from_millis(50));
+        for _ in 0..3 {
+            client.write(b"again\n").unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }
+        let (stream, _) = listener.accept().unwrap();
+        assert_eq!(read_line(stream), "again\n");
+
+        // Kill the listener. The first writes may still succeed, until the closed connection
+        // shows and reconnecting fails
+        drop(listener);
+        thread::sleep(Duration::from_millis(50));
+        assert!((0..10).any(|_| client.write(b"lost\n").is_err()));
+
+        // No attempt is made before the delay has passed, even though the listener is back
+        let listener = net::TcpListener::bind(addr).unwrap();
+        assert_eq!(client.write(b"lost\n").unwrap_err().kind(), io::ErrorKind::NotConnected);
+
+        thread::sleep(Duration::from_millis(300));
+        client.write(b"second\n").unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        assert_eq!(read_line(stream), "second\n");
+    }
+
+    /// Writes at most 3 bytes per call and fails every other call with `Interrupted`
+    struct Choppy {
+        out: Vec<u8>,
+        calls: usize,
+        /// Fails with `WouldBlock` after the write timeout once this many bytes have been written
+        capacity: usize,
+        /// How long each successful write takes
+        delay: Duration,
+        timeout: std::cell::Cell<Option<Duration>>,
+    }
+
+    impl Choppy {
+        fn new(capacity: usize, delay: Duration) -> Self {
+            Choppy { out: Vec::new(), calls: 0, capacity, delay, timeout: Default::default() }
+        }
+    }
+
+    impl Write for Choppy {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.calls += 1;
+            if self.calls % 2 == 1 {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            let len = buf.len().min(3).min(self.capacity - self.out.len());
+            if len == 0 {
+                std::thread::sleep(self.timeout.get().unwrap());
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
+            std::thread::sleep(self.delay);
+            self.out.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl TimedWrite for Choppy {
+        fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+            self.timeout.set(timeout);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_write_all_before() {
+        let line = b"measurement,tag=value field=10i\n";
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let mut choppy = Choppy::new(usize::MAX, Duration::ZERO);
+        assert!(write_all_before(&mut choppy, line, deadline).is_ok());
+        assert_eq!(choppy.out, &line[..]);
+        assert!(choppy.timeout.get().unwrap() <= Duration::from_secs(60));
+
+        let mut choppy = Choppy::new(5, Duration::ZERO);
+        let (written, err) = write_all_before(&mut choppy, line, Instant::now() + Duration::from_millis(50)).unwrap_err();
+        assert_eq!((written, err.kind()), (5, io::ErrorKind::TimedOut));
+
+        // A writer that keeps accepting a few bytes does not extend the deadline
+        let start = Instant::now();
+        let mut choppy = Choppy::new(usize::MAX, Duration::from_millis(20));
+        let (written, err) = write_all_before(&mut choppy, line, start + Duration::from_millis(50)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(written < line.len() && start.elapsed() < Duration::from_millis(200), "{}", written);
+    }
+
+    #[test]
+    fn test_tcp_partial_write() {
+        use std::io::Read;
+        use std::thread;
+
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = Client::new(format!("tcp://{}", listener.local_addr().unwrap())).unwrap();
+        let mut client = client.write_timeout(Duration::from_millis(100)).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+
+        // The receiver does not read, so the line is too long for the socket buffers
+        let mut line = vec![b'a'; 64 << 20];
+        line.extend_from_slice(b" f=1i\n");
+        assert_eq!(client.write(&line).unwrap_err().kind(), io::ErrorKind::TimedOut);
+
+        let reader = thread::spawn(move || {
+            let mut received = Vec::new();
+            while !received.ends_with(b"second f=2i\n") {
+                let mut buf = [0; 1 << 16];
+                let read = stream.read(&mut buf).unwrap();
+                assert!(read > 0);
+                received.extend_from_slice(&buf[..read]);
+            }
+            received
+        });
+
+        // The rest of the first line is written before the second line
+        let mut client = client.write_timeout(Duration::from_secs(10)).unwrap();
+        client.write(b"second f=2i\n").unwrap();
+        let received = reader.join().unwrap();
+        assert_eq!(received.len(), line.len() + 12);
+        assert!(received.starts_with(&line));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_reconnect() {
+        use std::io::{BufRead, BufReader};
+        use std::os::unix::net::UnixListener;
+        use std::{fs, thread};
+
+        let read_line = |stream: UnixStream| {
+            let mut line = String::new();
+            BufReader::new(stream).read_line(&mut line).unwrap();
+            line
+        };
+
+        let dir = temp_dir("unix");
+        let path = dir.join("telegraf.sock");
+
+        let url = format!("unix://{}", path.display());
+        assert!(Client::new(url.clone()).is_err());
+
+        let listener = UnixListener::bind(&path).unwrap();
+        let backoff = Backoff { initial: Duration::from_millis(300), jitter: 0.0, ..Backoff::default() };
+        let mut client = Client::new(url).unwrap().backoff(backoff);
+
+        let (stream, _) = listener.accept().unwrap();
+        client.write(b"first\n").unwrap();
+        assert_eq!(read_line(stream), "first\n");
+
+        // Kill the listener, the closed connection shows on the next write and reconnecting fails
+        drop(listener);
+        fs::remove_file(&path).unwrap();
+        assert!(client.write(b"lost\n").is_err());
+
+        let listener = UnixListener::bind(&path).unwrap();
+        assert_eq!(client.write(b"lost\n").unwrap_err().kind(), io::ErrorKind::NotConnected);
+
+        thread::sleep(Duration::from_millis(300));
+        client.write(b"second\n").unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        assert_eq!(read_line(stream), "second\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Creates an empty directory for the sockets of a test
+    #[cfg(unix)]
+    fn temp_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("slog-telegraf-{}-{}", test, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unixgram() {
+        use std::fs;
+
+        let dir = temp_dir("unixgram");
+        let path = dir.join("telegraf.sock");
+        let url = format!("unixgram://{}", path.display());
+        assert!(Client::new(url.clone()).is_err());
+
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        let dropped = Arc::new(DroppedDatagrams::default());
+        let mut client = Client::new(url).unwrap().max_datagram_size(16).dropped_datagrams(dropped.clone());
+        let recv = |receiver: &UnixDatagram| {
+            let mut buf = [0; 64];
+            let len = receiver.recv(&mut buf).unwrap();
+            buf[..len].to_vec()
+        };
+
+        client.write(b"m f=1i\n").unwrap();
+        assert_eq!(recv(&receiver), b"m f=1i\n");
+
+        assert_eq!(client.write(b"m f=\"too long line\"\n").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!((dropped.too_large(), dropped.failed()), (1, 0));
+
+        // Restart the receiver
+        drop(receiver);
+        fs::remove_file(&path).unwrap();
+        assert!(client.write(b"m f=2i\n").is_err());
+        assert_eq!((dropped.too_large(), dropped.failed()), (1, 1));
+
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        client.write(b"m f=3i\n").unwrap();
+        assert_eq!(recv(&receiver), b"m f=3i\n");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
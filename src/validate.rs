@@ -0,0 +1,183 @@
+use crate::error::LineError;
+
+/// Keys that InfluxDB reserves for itself
+const RESERVED_KEYS: [&str; 3] = ["time", "_field", "_measurement"];
+
+/// Checks a finished line against the line protocol
+///
+/// Reference: https://docs.influxdata.com/influxdb/v1.8/write_protocols/line_protocol_reference/
+pub(crate) fn validate(line: &str, max_length: Option<usize>) -> Result<(), LineError> {
+    if let Some(max_length) = max_length {
+        if line.len() > max_length {
+            return Err(LineError::TooLong { length: line.len(), max_length });
+        }
+    }
+
+    let line = match line.strip_suffix('\n') {
+        Some(line) => line,
+        None => return Err(LineError::Malformed("The line must end with a line break".into()))
+    };
+
+    let mut parser = Parser { rest: line };
+
+    let (measurement, mut separator) = parser.element(&[',', ' '], &[])?;
+    if measurement.is_empty() {
+        return Err(LineError::EmptyMeasurement);
+    }
+
+    while separator == Some(',') {
+        let key = parser.key()?;
+        let (value, next) = parser.element(&[',', ' '], &['='])?;
+        if value.is_empty() {
+            return Err(LineError::EmptyTagValue(key.into()));
+        }
+        separator = next;
+    }
+
+    if separator != Some(' ') || parser.rest.is_empty() {
+        return Err(LineError::MissingFields);
+    }
+
+    loop {
+        let key = parser.key()?;
+        let (value, next) = parser.field_value()?;
+        if !is_valid_field_value(value) {
+            return Err(LineError::InvalidFieldValue(key.into()));
+        }
+
+        match next {
+            Some(',') => continue,
+            Some(_) => break,
+            None => return Ok(())
+        }
+    }
+
+    let timestamp = parser.rest.strip_prefix('-').unwrap_or(parser.rest);
+    if timestamp.is_empty() || !timestamp.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(LineError::InvalidTimestamp);
+    }
+
+    Ok(())
+}
+
+fn is_valid_field_value(value: &str) -> bool {
+    if value.starts_with('"') {
+        // Strings are already checked while parsing
+        return true;
+    }
+
+    if let Some(integer) = value.strip_suffix('i') {
+        return integer.parse::<i64>().is_ok();
+    }
+    if let Some(integer) = value.strip_suffix('u') {
+        return integer.parse::<u64>().is_ok();
+    }
+
+    match value {
+        "t" | "T" | "true" | "True" | "TRUE" | "f" | "F" | "false" | "False" | "FALSE" => true,
+        // Rust also parses 'NaN' and 'inf', which InfluxDB rejects
+        _ => value.parse::<f64>().map_or(false, f64::is_finite)
+    }
+}
+
+struct Parser<'a> {
+    rest: &'a str,
+}
+
+impl<'a> Parser<'a> {
+    /// Returns the escaped element up to the next unescaped separator and the separator found,
+    /// or `None` at the end of the line. The forbidden characters and line breaks must not appear
+    /// unescaped.
+    fn element(&mut self, separators: &[char], forbidden: &[char]) -> Result<(&'a str, Option<char>), LineError> {
+        let mut chars = self.rest.char_indices();
+        while let Some((i, c)) = chars.next() {
+            if c == '\\' {
+                chars.next();
+            } else if separators.contains(&c) {
+                let element = &self.rest[..i];
+                self.rest = &self.rest[i + 1..];
+                return Ok((element, Some(c)));
+            } else if forbidden.contains(&c) || c == '\n' {
+                return Err(LineError::Malformed(format!("Unexpected '{}' in '{}'", c, self.rest)));
+            }
+        }
+
+        let element = self.rest;
+        self.rest = "";
+        Ok((element, None))
+    }
+
+    /// Returns the escaped key of a tag or field and skips the following equal sign
+    fn key(&mut self) -> Result<&'a str, LineError> {
+        let key = match self.element(&['='], &[',', ' '])? {
+            (key, Some('=')) => key,
+            (key, _) => return Err(LineError::Malformed(format!("The key '{}' has no value", key)))
+        };
+
+        if key.is_empty() {
+            Err(LineError::EmptyKey)
+        } else if RESERVED_KEYS.contains(&key) {
+            Err(LineError::ReservedKey(key.into()))
+        } else {
+            Ok(key)
+        }
+    }
+
+    /// Returns the raw field value, including the quotes of strings
+    fn field_value(&mut self) -> Result<(&'a str, Option<char>), LineError> {
+        if !self.rest.starts_with('"') {
+            return self.element(&[',', ' '], &['=', '"']);
+        }
+
+        let mut chars = self.rest.char_indices().skip(1);
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => { chars.next(); },
+                '"' => {
+                    let value = &self.rest[..=i];
+                    let next = self.rest[i + 1..].chars().next();
+                    if next.is_some() && next != Some(',') && next != Some(' ') {
+                        return Err(LineError::Malformed(format!("Unexpected '{}' after string", self.rest)));
+                    }
+                    self.rest = &self.rest[(i + 2).min(self.rest.len())..];
+                    return Ok((value, next));
+                },
+                _ => {}
+            }
+        }
+
+        Err(LineError::Malformed("Unterminated string".into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(validate("m,a=b,c\\ d=e\\,f x=1i,y=-1.5,z=\"a \\\"b\\\\\",u=3u,b=t 1600000000\n", None), Ok(()));
+        assert_eq!(validate("m x=1\n", Some(7)), Ok(()));
+        assert_eq!(validate("m x=1\n", Some(5)), Err(LineError::TooLong { length: 6, max_length: 5 }));
+
+        assert_eq!(validate("m x=\"a\nb\"\n", None), Ok(()));
+        assert_eq!(validate("m x=1", None), Err(LineError::Malformed("The line must end with a line break".into())));
+        assert!(matches!(validate("m,a=b\nc x=1\n", None), Err(LineError::Malformed(_))));
+        assert!(matches!(validate("m x=1\nm x=1\n", None), Err(LineError::Malformed(_))));
+        assert_eq!(validate(",a=b x=1\n", None), Err(LineError::EmptyMeasurement));
+        assert_eq!(validate("m,=b x=1\n", None), Err(LineError::EmptyKey));
+        assert_eq!(validate("m,a= x=1\n", None), Err(LineError::EmptyTagValue("a".into())));
+        assert_eq!(validate("m,time=b x=1\n", None), Err(LineError::ReservedKey("time".into())));
+        assert_eq!(validate("m,a=b\n", None), Err(LineError::MissingFields));
+        assert_eq!(validate("m,a=b \n", None), Err(LineError::MissingFields));
+        assert_eq!(validate("m _field=1\n", None), Err(LineError::ReservedKey("_field".into())));
+        assert_eq!(validate("m x=NaN\n", None), Err(LineError::InvalidFieldValue("x".into())));
+        assert_eq!(validate("m x=1.5i\n", None), Err(LineError::InvalidFieldValue("x".into())));
+        assert_eq!(validate("m x=-1u\n", None), Err(LineError::InvalidFieldValue("x".into())));
+        assert_eq!(validate("m x=yes\n", None), Err(LineError::InvalidFieldValue("x".into())));
+        assert!(matches!(validate("m x=\"abc\n", None), Err(LineError::Malformed(_))));
+        assert!(matches!(validate("m x=\"abc\"d\n", None), Err(LineError::Malformed(_))));
+        assert!(matches!(validate("m,a=b=c x=1\n", None), Err(LineError::Malformed(_))));
+        assert_eq!(validate("m x=1 now\n", None), Err(LineError::InvalidTimestamp));
+    }
+}
@@ -0,0 +1,115 @@
+use std::cell::Cell;
+use std::fmt;
+use std::time::SystemTime;
+use slog::{Key, Record, Serializer, Value};
+use crate::routing::Placement;
+use crate::ser::Precision;
+
+thread_local! {
+    static DIRECTIVES: Cell<Directives> = const { Cell::new(Directives::NONE) };
+}
+
+/// How the value being serialized is written, set by the wrapper values while they serialize
+/// their inner value. Serializers other than the ones of this crate are not affected
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Directives {
+    pub placement: Option<Placement>,
+    /// Writes the integer with the `u` suffix, see `Unsigned`
+    pub unsigned: bool,
+    /// Sets the time of the line instead of writing the value, see `Timestamp`
+    pub timestamp: Option<SystemTime>,
+    /// Sets the measurement instead of writing the value, see `Measurement`
+    pub measurement: bool,
+}
+
+impl Directives {
+    pub const NONE: Directives = Directives { placement: None, unsigned: false, timestamp: None, measurement: false };
+
+    /// The directives of the wrapper values currently being serialized on this thread
+    pub fn current() -> Directives {
+        DIRECTIVES.with(Cell::get)
+    }
+}
+
+/// Restores the previous directives when dropped, even if serializing panics
+struct Restore(Directives);
+
+impl Drop for Restore {
+    fn drop(&mut self) {
+        DIRECTIVES.with(|directives| directives.set(self.0));
+    }
+}
+
+/// Calls `serialize` with the directives changed by `change`
+fn directed<C, S>(change: C, serialize: S) -> slog::Result
+    where C: FnOnce(&mut Directives), S: FnOnce() -> slog::Result
+{
+    let previous = Directives::current();
+    let mut directives = previous;
+    change(&mut directives);
+    DIRECTIVES.with(|current| current.set(directives));
+
+    let _restore = Restore(previous);
+    serialize()
+}
+
+/// Writes the value as tag, regardless of the routing, e.g. `info!(log, "done"; "region" => Tag(region))`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tag<T>(pub T);
+
+impl<T: Value> Value for Tag<T> {
+    fn serialize(&self, record: &Record, key: Key, serializer: &mut dyn Serializer) -> slog::Result {
+        directed(|directives| directives.placement = Some(Placement::Tag),
+                 || self.0.serialize(record, key, serializer))
+    }
+}
+
+/// Writes the value as field, regardless of the routing, e.g. `o!("request_id" => Field(id))`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Field<T>(pub T);
+
+impl<T: Value> Value for Field<T> {
+    fn serialize(&self, record: &Record, key: Key, serializer: &mut dyn Serializer) -> slog::Result {
+        directed(|directives| directives.placement = Some(Placement::Field),
+                 || self.0.serialize(record, key, serializer))
+    }
+}
+
+/// Writes the integer as native unsigned integer field, e.g. `value=10u`, regardless of
+/// `UnsignedIntegers`. Requires InfluxDB 1.8+ or 2.x
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Unsigned(pub u64);
+
+impl Value for Unsigned {
+    fn serialize(&self, _record: &Record, key: Key, serializer: &mut dyn Serializer) -> slog::Result {
+        directed(|directives| directives.unsigned = true, || serializer.emit_u64(key, self.0))
+    }
+}
+
+/// Sets the time of the line instead of writing a tag or field
+///
+/// Other serializers get the nanoseconds since the unix epoch.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Timestamp(pub SystemTime);
+
+impl Value for Timestamp {
+    fn serialize(&self, _record: &Record, key: Key, serializer: &mut dyn Serializer) -> slog::Result {
+        let nanos = Precision::Nanoseconds.timestamp(self.0);
+        directed(|directives| directives.timestamp = Some(self.0),
+                 || serializer.emit_arguments(key, &format_args!("{}", nanos)))
+    }
+}
+
+/// Sets the measurement of the line instead of writing a tag or field
+///
+/// Record values take precedence over logger values, and both over
+/// `TelegrafDrainBuilder::measurement_key` and `TelegrafDrainBuilder::measurement_fn`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Measurement<S>(pub S);
+
+impl<S: fmt::Display> Value for Measurement<S> {
+    fn serialize(&self, _record: &Record, key: Key, serializer: &mut dyn Serializer) -> slog::Result {
+        directed(|directives| directives.measurement = true,
+                 || serializer.emit_arguments(key, &format_args!("{}", self.0)))
+    }
+}